@@ -0,0 +1,22 @@
+#version 330 core
+
+in vec2 texture_coords;
+
+out vec4 out_colour;
+
+uniform sampler2D bloom_sampler;
+// 1 to blur along x, 0 to blur along y, the two passes together make a gaussian blur
+uniform float horizontal;
+
+const float WEIGHTS[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+void main() {
+    vec2 texel_size = 1.0 / textureSize(bloom_sampler, 0);
+    vec2 offset = horizontal > 0.5 ? vec2(texel_size.x, 0.0) : vec2(0.0, texel_size.y);
+    vec3 result = texture(bloom_sampler, texture_coords).rgb * WEIGHTS[0];
+    for (int i = 1; i < 5; i++) {
+        result += texture(bloom_sampler, texture_coords + offset * float(i)).rgb * WEIGHTS[i];
+        result += texture(bloom_sampler, texture_coords - offset * float(i)).rgb * WEIGHTS[i];
+    }
+    out_colour = vec4(result, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 330 core
+
+in vec2 texture_coords;
+
+out vec4 out_colour;
+
+uniform sampler2D scene_sampler;
+uniform float bloom_threshold;
+
+void main() {
+    vec3 colour = texture(scene_sampler, texture_coords).rgb;
+    float brightness = dot(colour, vec3(0.2126, 0.7152, 0.0722));
+    // only what is brighter than the threshold, scaled so it fades in rather than popping
+    float contribution = max(brightness - bloom_threshold, 0.0) / max(brightness, 0.0001);
+    out_colour = vec4(colour * contribution, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 330 core
+#include "frame-uniforms.glsl"
+
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec3 colour;
+
+out vec3 line_colour;
+
+void main() {
+    gl_Position = projection_matrix * view_matrix * vec4(position, 1.0);
+    line_colour = colour;
+}
@@ -1,47 +1,38 @@
 #version 330 core
+#include "lighting.glsl"
+#include "fog.glsl"
+#include "render-modes.glsl"
+
 in vec3 colour;
 in vec2 passed_texture_coords;
 in vec3 pos;
 in vec3 surface_normal;
-in vec3 to_light_vector;
-in vec3 to_camera_vector;
 in float visibility;
 
 out vec4 out_colour;
 
 uniform sampler2D texture_sampler;
-uniform vec3 light_colour;
-uniform float reflectivity;
-uniform float shine_damper;
-uniform vec3 day_sky_colour;
-uniform vec3 night_sky_colour;
-uniform float blend_factor;
+uniform vec3 diffuse_colour;
 
 
 void main() {
-    vec3 unit_normal = normalize(surface_normal);
-    vec3 unit_light_vector = normalize(to_light_vector);
-    vec3 unit_camera_vector = normalize(to_camera_vector);
-
-    vec3 light_direction = -unit_light_vector;
-    vec3 reflected_light_direction = reflect(light_direction, unit_normal);
-    float specular_factor = dot(reflected_light_direction, unit_camera_vector);
-    specular_factor = max(specular_factor, 0.0);
-    float damp_factor = pow(specular_factor, shine_damper);
-    vec3 specular_light = damp_factor * reflectivity * light_colour;
+    vec4 texture_colour = texture(texture_sampler, passed_texture_coords);
+    if (texture_colour.a < 0.5) {
+        discard;
+    }
 
-    float nDot1 = dot(unit_normal, unit_light_vector);
-    float brightness = max(nDot1, 0.0);
-    vec3 diffuse = brightness * light_colour;
+    vec3 unit_normal = normalize(surface_normal);
+    vec3 diffuse;
+    vec3 specular_light;
+    calculate_lighting(unit_normal, diffuse, specular_light);
 
     //vec3 pos_norm = normalize(debug_var);
 
     //out_colour = vec4(pos_norm, 1.0);
-    vec3 final_sky_colour = mix(day_sky_colour, night_sky_colour, blend_factor);
-
-    out_colour = vec4(specular_light, 1.0) + vec4(diffuse, 1.0) * texture(texture_sampler, passed_texture_coords);
-    out_colour = mix(vec4(final_sky_colour, 1.0), out_colour, visibility);
+    out_colour = vec4(specular_light, 1.0) + vec4(diffuse, 1.0) * vec4(diffuse_colour, 1.0) * texture_colour;
+    out_colour = apply_fog(out_colour, visibility);
+    out_colour = apply_render_mode(out_colour, unit_normal, passed_texture_coords, diffuse + specular_light);
     //out_colour = vec4(pos, 1.0);
     //out_colour = vec4(diffuse, 1.0) * texture(texture_sampler, passed_texture_coords);
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,8 @@
+// distance fog, the vertex half: how much of the surface shows through the fog
+#include "frame-uniforms.glsl"
+
+float fog_visibility(vec4 position_relative_to_camera) {
+    float distance = length(position_relative_to_camera.xyz);
+    float visibility = exp(-pow((distance * fog_density), fog_gradient));
+    return clamp(visibility, 0.0, 1.0);
+}
@@ -0,0 +1,7 @@
+// distance fog, the fragment half: fades into the sky colour for the time of day
+#include "frame-uniforms.glsl"
+
+vec4 apply_fog(vec4 colour, float visibility) {
+    vec3 final_sky_colour = mix(day_sky_colour.rgb, night_sky_colour.rgb, blend_factor);
+    return mix(vec4(final_sky_colour, 1.0), colour, visibility);
+}
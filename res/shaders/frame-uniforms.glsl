@@ -0,0 +1,22 @@
+// uploaded once for each scene pass by FrameUniformUploader, needs MAX_LIGHTS defined
+layout (std140) uniform FrameUniforms {
+    mat4 projection_matrix;
+    mat4 view_matrix;
+    mat4 light_space_matrix;
+    // vec3s are padded to vec4s in a uniform block, w is unused
+    vec4 camera_position;
+    // the sun first, then the point lights nearest the camera
+    vec4 light_position[MAX_LIGHTS];
+    vec4 light_colour[MAX_LIGHTS];
+    vec4 attenuation[MAX_LIGHTS];
+    vec4 day_sky_colour;
+    vec4 night_sky_colour;
+    float blend_factor;
+    float fog_density;
+    float fog_gradient;
+    float shadow_bias;
+    float near_plane;
+    float far_plane;
+    // seconds since the start
+    float time;
+};
@@ -0,0 +1,39 @@
+#version 330 core
+
+in vec2 texture_coords;
+
+out vec4 out_colour;
+
+uniform sampler2D scene_sampler;
+
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+// furthest in pixels an edge is searched along
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+// fast approximate anti-aliasing, blurs along edges found from the luminance of the neighbours
+void main() {
+    vec2 texel_size = 1.0 / textureSize(scene_sampler, 0);
+    float luma_nw = dot(texture(scene_sampler, texture_coords + vec2(-1.0, -1.0) * texel_size).rgb, LUMA);
+    float luma_ne = dot(texture(scene_sampler, texture_coords + vec2(1.0, -1.0) * texel_size).rgb, LUMA);
+    float luma_sw = dot(texture(scene_sampler, texture_coords + vec2(-1.0, 1.0) * texel_size).rgb, LUMA);
+    float luma_se = dot(texture(scene_sampler, texture_coords + vec2(1.0, 1.0) * texel_size).rgb, LUMA);
+    float luma_m = dot(texture(scene_sampler, texture_coords).rgb, LUMA);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // across the direction of the steepest change in luminance
+    vec2 direction = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float inverse_direction_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * inverse_direction_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel_size;
+
+    vec3 near_average = 0.5 * (texture(scene_sampler, texture_coords + direction * (1.0 / 3.0 - 0.5)).rgb
+                             + texture(scene_sampler, texture_coords + direction * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 far_average = near_average * 0.5 + 0.25 * (texture(scene_sampler, texture_coords + direction * -0.5).rgb
+                                                  + texture(scene_sampler, texture_coords + direction * 0.5).rgb);
+    float luma_far = dot(far_average, LUMA);
+    // the wider sample ran off the edge, fall back to the narrower one
+    out_colour = vec4((luma_far < luma_min || luma_far > luma_max) ? near_average : far_average, 1.0);
+}
@@ -0,0 +1,16 @@
+// the vertex half of lighting.glsl
+#include "frame-uniforms.glsl"
+
+out vec3 to_light_vector[MAX_LIGHTS];
+out vec3 to_camera_vector;
+out vec4 shadow_coords;
+
+void pass_lighting(vec4 world_position) {
+    // from clip space -1..1 to texture coordinates and depth 0..1
+    shadow_coords = light_space_matrix * world_position;
+    shadow_coords = vec4(shadow_coords.xyz * 0.5 + 0.5, 1.0);
+    for (int i = 0; i < MAX_LIGHTS; i++) {
+        to_light_vector[i] = light_position[i].xyz - world_position.xyz;
+    }
+    to_camera_vector = camera_position.xyz - world_position.xyz;
+}
@@ -0,0 +1,59 @@
+// the sun in the first slot with filtered shadows, then point lights
+#include "frame-uniforms.glsl"
+
+in vec3 to_light_vector[MAX_LIGHTS];
+in vec3 to_camera_vector;
+in vec4 shadow_coords;
+
+uniform float reflectivity;
+uniform float shine_damper;
+uniform sampler2D shadow_map_sampler;
+
+const int PCF_RADIUS = 1;
+const float SHADOW_DARKNESS = 0.5;
+
+// fraction of the surrounding shadow map texels closer to the sun than this fragment
+float shadow_factor() {
+    if (shadow_coords.z > 1.0) {
+        return 0.0;
+    }
+    vec2 texel_size = 1.0 / textureSize(shadow_map_sampler, 0);
+    float shadow = 0.0;
+    for (int x = -PCF_RADIUS; x <= PCF_RADIUS; x++) {
+        for (int y = -PCF_RADIUS; y <= PCF_RADIUS; y++) {
+            float closest_depth = texture(shadow_map_sampler, shadow_coords.xy + vec2(x, y) * texel_size).r;
+            if (shadow_coords.z - shadow_bias > closest_depth) {
+                shadow += 1.0;
+            }
+        }
+    }
+    float sample_count = (PCF_RADIUS * 2 + 1) * (PCF_RADIUS * 2 + 1);
+    return shadow / sample_count;
+}
+
+void calculate_lighting(vec3 unit_normal, out vec3 diffuse, out vec3 specular_light) {
+    vec3 unit_camera_vector = normalize(to_camera_vector);
+    float shadow = shadow_factor();
+
+    diffuse = vec3(0.0);
+    specular_light = vec3(0.0);
+    for (int i = 0; i < MAX_LIGHTS; i++) {
+        float distance = length(to_light_vector[i]);
+        vec3 light_attenuation = attenuation[i].xyz;
+        float attenuation_factor = light_attenuation.x + (light_attenuation.y * distance) + (light_attenuation.z * distance * distance);
+        vec3 unit_light_vector = normalize(to_light_vector[i]);
+        // only the sun in the first slot casts shadows
+        float lit = i == 0 ? 1.0 - shadow : 1.0;
+
+        vec3 light_direction = -unit_light_vector;
+        vec3 reflected_light_direction = reflect(light_direction, unit_normal);
+        float specular_factor = dot(reflected_light_direction, unit_camera_vector);
+        specular_factor = max(specular_factor, 0.0);
+        float damp_factor = pow(specular_factor, shine_damper);
+        specular_light += damp_factor * reflectivity * light_colour[i].rgb * lit / attenuation_factor;
+
+        float nDot1 = dot(unit_normal, unit_light_vector);
+        float brightness = max(nDot1, 0.0) * (i == 0 ? 1.0 - shadow * SHADOW_DARKNESS : 1.0);
+        diffuse += brightness * light_colour[i].rgb / attenuation_factor;
+    }
+}
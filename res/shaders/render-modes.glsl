@@ -0,0 +1,27 @@
+// what to draw in place of normal shading, see RenderMode
+uniform int render_mode;
+const int RENDER_MODE_NORMALS = 2;
+const int RENDER_MODE_UV_CHECKER = 3;
+const int RENDER_MODE_LIGHTING_ONLY = 4;
+const int RENDER_MODE_OVERDRAW = 5;
+const vec4 OVERDRAW_COLOUR = vec4(0.1, 0.05, 0.02, 1.0);
+
+// a grey checkerboard with eight squares per texture repeat, tinted by the coordinates within it
+vec3 uv_checker(vec2 texture_coords) {
+    vec2 cell = floor(texture_coords * 8.0);
+    float checker = mod(cell.x + cell.y, 2.0) * 0.6 + 0.2;
+    return mix(vec3(checker), vec3(fract(texture_coords), 0.0), 0.3);
+}
+
+vec4 apply_render_mode(vec4 colour, vec3 unit_normal, vec2 texture_coords, vec3 lighting) {
+    if (render_mode == RENDER_MODE_NORMALS) {
+        return vec4(unit_normal * 0.5 + 0.5, 1.0);
+    } else if (render_mode == RENDER_MODE_UV_CHECKER) {
+        return vec4(uv_checker(texture_coords), 1.0);
+    } else if (render_mode == RENDER_MODE_LIGHTING_ONLY) {
+        return vec4(lighting, 1.0);
+    } else if (render_mode == RENDER_MODE_OVERDRAW) {
+        return OVERDRAW_COLOUR;
+    }
+    return colour;
+}
@@ -0,0 +1,11 @@
+#version 330 core
+in vec2 passed_texture_coords;
+
+uniform sampler2D texture_sampler;
+
+void main() {
+    // cut-out foliage only casts the shape of its leaves
+    if (texture(texture_sampler, passed_texture_coords).a < 0.5) {
+        discard;
+    }
+}
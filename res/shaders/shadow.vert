@@ -0,0 +1,13 @@
+#version 330 core
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec2 texture_coords;
+layout (location = 3) in mat4 transformation_matrix;
+
+out vec2 passed_texture_coords;
+
+uniform mat4 light_space_matrix;
+
+void main() {
+    gl_Position = light_space_matrix * transformation_matrix * vec4(position, 1.0);
+    passed_texture_coords = texture_coords;
+}
@@ -1,17 +1,23 @@
 #version 330
+#include "frame-uniforms.glsl"
 
 in vec3 texture_coords;
 out vec4 out_Color;
 
 uniform samplerCube day_cube_map;
 uniform samplerCube night_cube_map;
-uniform vec3 day_sky_colour;
-uniform vec3 night_sky_colour;
-uniform float blend_factor;
 
 const float lower_limit = 0.0;
 const float upper_limit = 30.0;
 
+// what to draw in place of normal shading, see RenderMode
+uniform int render_mode;
+const int RENDER_MODE_NORMALS = 2;
+const int RENDER_MODE_UV_CHECKER = 3;
+const int RENDER_MODE_LIGHTING_ONLY = 4;
+const int RENDER_MODE_OVERDRAW = 5;
+const vec4 OVERDRAW_COLOUR = vec4(0.1, 0.05, 0.02, 1.0);
+
 void main(void){
     vec4 day_texture = texture(day_cube_map, normalize(texture_coords));
     vec4 night_texture = texture(night_cube_map, normalize(texture_coords));
@@ -19,8 +25,22 @@ void main(void){
 
     float factor = (texture_coords.y - lower_limit) / (upper_limit - lower_limit);
     factor = clamp(factor, 0.0, 1.0);
-    vec3 final_sky_colour = mix(day_sky_colour, night_sky_colour, blend_factor);
+    vec3 final_sky_colour = mix(day_sky_colour.rgb, night_sky_colour.rgb, blend_factor);
 
     out_Color = mix(vec4(final_sky_colour, 1.0), final_color, factor);
+
+    vec3 direction = normalize(texture_coords);
+    if (render_mode == RENDER_MODE_NORMALS) {
+        // facing inwards, towards the camera
+        out_Color = vec4(-direction * 0.5 + 0.5, 1.0);
+    } else if (render_mode == RENDER_MODE_UV_CHECKER) {
+        vec3 cell = floor(direction * 8.0);
+        out_Color = vec4(vec3(mod(cell.x + cell.y + cell.z, 2.0) * 0.6 + 0.2), 1.0);
+    } else if (render_mode == RENDER_MODE_LIGHTING_ONLY) {
+        // the sky isn't lit, a flat grey keeps it out of the way
+        out_Color = vec4(vec3(0.5), 1.0);
+    } else if (render_mode == RENDER_MODE_OVERDRAW) {
+        out_Color = OVERDRAW_COLOUR;
+    }
 //    out_Color = vec4(normalize(texture_coords), 1.0);
 }
\ No newline at end of file
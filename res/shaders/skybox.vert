@@ -1,12 +1,18 @@
 #version 330
+#include "frame-uniforms.glsl"
+
 layout (location = 0) in vec3 position;
 
 out vec3 texture_coords;
 
-uniform mat4 projection_matrix;
-uniform mat4 view_matrix;
+// the sky turns slowly about the vertical
+uniform mat4 rotation_matrix;
 
 void main(void){
-	gl_Position = projection_matrix * view_matrix * vec4(position, 1.0);
+	// without the translation, the sky stays around the camera wherever it goes
+	mat4 sky_view_matrix = mat4(mat3(view_matrix)) * rotation_matrix;
+	gl_Position = projection_matrix * sky_view_matrix * vec4(position, 1.0);
 	texture_coords = position;
+	// the sky is never clipped, it is reflected in the water whatever the water height
+	gl_ClipDistance[0] = 1.0;
 }
\ No newline at end of file
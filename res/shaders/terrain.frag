@@ -1,10 +1,12 @@
 #version 330 core
+#include "lighting.glsl"
+#include "fog.glsl"
+#include "render-modes.glsl"
+
 in vec3 colour;
 in vec2 passed_texture_coords;
 in vec3 pos;
 in vec3 surface_normal;
-in vec3 to_light_vector;
-in vec3 to_camera_vector;
 in float visibility;
 
 out vec4 out_colour;
@@ -14,12 +16,6 @@ uniform sampler2D r_sampler;
 uniform sampler2D g_sampler;
 uniform sampler2D b_sampler;
 uniform sampler2D blend_map_sampler;
-uniform vec3 light_colour;
-uniform float reflectivity;
-uniform float shine_damper;
-uniform vec3 day_sky_colour;
-uniform vec3 night_sky_colour;
-uniform float blend_factor;
 
 
 void main() {
@@ -36,30 +32,20 @@ void main() {
 
 
     vec3 unit_normal = normalize(surface_normal);
-    vec3 unit_light_vector = normalize(to_light_vector);
-    vec3 unit_camera_vector = normalize(to_camera_vector);
-
-    vec3 light_direction = -unit_light_vector;
-    vec3 reflected_light_direction = reflect(light_direction, unit_normal);
-    float specular_factor = dot(reflected_light_direction, unit_camera_vector);
-    specular_factor = max(specular_factor, 0.0);
-    float damp_factor = pow(specular_factor, shine_damper);
-    vec3 specular_light = damp_factor * reflectivity * light_colour;
-
-    float nDot1 = dot(unit_normal, unit_light_vector);
-    float brightness = max(nDot1, 0.0);
-    vec3 diffuse = brightness * light_colour;
+    vec3 diffuse;
+    vec3 specular_light;
+    calculate_lighting(unit_normal, diffuse, specular_light);
 
     //vec3 pos_norm = normalize(debug_var);
 
     //out_colour = vec4(pos_norm, 1.0);
 
-    vec3 final_sky_colour = mix(day_sky_colour, night_sky_colour, blend_factor);
     out_colour = vec4(specular_light, 1.0) + vec4(diffuse, 1.0) * final_colour;
-    out_colour = mix(vec4(final_sky_colour, 1.0), out_colour, visibility);
+    out_colour = apply_fog(out_colour, visibility);
+    out_colour = apply_render_mode(out_colour, unit_normal, tiled_coords, diffuse + specular_light);
 
     //out_colour = vec4(pos, 1.0);
     //out_colour = final_colour;
     //out_colour = vec4(diffuse, 1.0) * texture(texture_sampler, passed_texture_coords);
 
-}
\ No newline at end of file
+}
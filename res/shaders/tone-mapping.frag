@@ -0,0 +1,29 @@
+#version 330 core
+
+in vec2 texture_coords;
+
+out vec4 out_colour;
+
+uniform sampler2D scene_sampler;
+uniform sampler2D bloom_sampler;
+uniform float use_bloom;
+uniform float bloom_intensity;
+uniform float use_tone_mapping;
+uniform float exposure;
+uniform float use_gamma_correction;
+uniform float gamma;
+
+void main() {
+    vec3 colour = texture(scene_sampler, texture_coords).rgb;
+    if (use_bloom > 0.5) {
+        colour += texture(bloom_sampler, texture_coords).rgb * bloom_intensity;
+    }
+    if (use_tone_mapping > 0.5) {
+        // exposure tone mapping, brings the whole hdr range into 0..1
+        colour = vec3(1.0) - exp(-colour * exposure);
+    }
+    if (use_gamma_correction > 0.5) {
+        colour = pow(colour, vec3(1.0 / gamma));
+    }
+    out_colour = vec4(clamp(colour, 0.0, 1.0), 1.0);
+}
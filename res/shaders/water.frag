@@ -0,0 +1,74 @@
+#version 330 core
+#include "fog.glsl"
+
+in vec4 clip_space;
+in vec2 texture_coords;
+in vec3 to_camera_vector;
+in vec3 from_light_vector;
+in float visibility;
+
+out vec4 out_colour;
+
+uniform sampler2D reflection_sampler;
+uniform sampler2D refraction_sampler;
+uniform sampler2D depth_sampler;
+uniform sampler2D dudv_sampler;
+uniform sampler2D normal_sampler;
+uniform float move_factor;
+
+const float WAVE_STRENGTH = 0.02;
+const float SHINE_DAMPER = 20.0;
+const float REFLECTIVITY = 0.5;
+// higher shows less of the reflection when looking straight down
+const float FRESNEL_POWER = 0.6;
+const vec4 WATER_COLOUR = vec4(0.0, 0.3, 0.5, 1.0);
+// depth over which the edges fade in and the distortion builds up
+const float SOFT_EDGE_DEPTH = 3.0;
+const float DISTORTION_DEPTH = 15.0;
+
+// distance from the camera of a depth buffer value
+float linear_depth(float depth) {
+    return 2.0 * near_plane * far_plane / (far_plane + near_plane - (2.0 * depth - 1.0) * (far_plane - near_plane));
+}
+
+void main() {
+    // screen position of this fragment, the reflection was rendered upside down
+    vec2 ndc = (clip_space.xy / clip_space.w) / 2.0 + 0.5;
+    vec2 refraction_coords = ndc;
+    vec2 reflection_coords = vec2(ndc.x, 1.0 - ndc.y);
+
+    float floor_distance = linear_depth(texture(depth_sampler, refraction_coords).r);
+    float water_distance = linear_depth(gl_FragCoord.z);
+    float water_depth = floor_distance - water_distance;
+
+    // the distortion map sampled twice, one moving with the other, so the ripples don't just slide along
+    vec2 distorted_texture_coords = texture(dudv_sampler, vec2(texture_coords.x + move_factor, texture_coords.y)).rg * 0.1;
+    distorted_texture_coords = texture_coords + vec2(distorted_texture_coords.x, distorted_texture_coords.y + move_factor);
+    vec2 total_distortion = (texture(dudv_sampler, distorted_texture_coords).rg * 2.0 - 1.0) * WAVE_STRENGTH;
+    // calm at the shore so the edge doesn't pick up what is above the water
+    total_distortion *= clamp(water_depth / DISTORTION_DEPTH, 0.0, 1.0);
+
+    refraction_coords = clamp(refraction_coords + total_distortion, 0.001, 0.999);
+    reflection_coords = clamp(reflection_coords + total_distortion, 0.001, 0.999);
+
+    vec4 reflection_colour = texture(reflection_sampler, reflection_coords);
+    vec4 refraction_colour = texture(refraction_sampler, refraction_coords);
+
+    vec3 normal_map_colour = texture(normal_sampler, distorted_texture_coords).rgb * 2.0 - 1.0;
+    // flattened so the water doesn't look too choppy
+    vec3 normal = normalize(vec3(normal_map_colour.x, normal_map_colour.y * 3.0, normal_map_colour.z));
+
+    vec3 view_vector = normalize(to_camera_vector);
+    float refractive_factor = pow(max(dot(view_vector, normal), 0.0), FRESNEL_POWER);
+
+    vec3 reflected_light = reflect(normalize(from_light_vector), normal);
+    float specular = pow(max(dot(reflected_light, view_vector), 0.0), SHINE_DAMPER);
+    vec3 specular_highlights = light_colour[0].rgb * specular * REFLECTIVITY * clamp(water_depth / SOFT_EDGE_DEPTH, 0.0, 1.0);
+
+    out_colour = mix(reflection_colour, refraction_colour, refractive_factor);
+    out_colour = mix(out_colour, WATER_COLOUR, 0.2) + vec4(specular_highlights, 0.0);
+
+    out_colour = apply_fog(out_colour, visibility);
+    // fade out where the water is shallow so the shore has no hard line
+    out_colour.a = clamp(water_depth / SOFT_EDGE_DEPTH, 0.0, 1.0);
+}
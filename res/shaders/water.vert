@@ -0,0 +1,28 @@
+#version 330 core
+#include "fog-vertex.glsl"
+
+layout (location = 0) in vec2 position;
+
+out vec4 clip_space;
+out vec2 texture_coords;
+out vec3 to_camera_vector;
+out vec3 from_light_vector;
+out float visibility;
+
+uniform mat4 transformation_matrix;
+
+// world units covered by one repeat of the wave textures
+const float WAVE_TILE_SIZE = 40.0;
+
+void main() {
+    vec4 world_position = transformation_matrix * vec4(position.x, 0.0, position.y, 1.0);
+    vec4 position_relative_to_camera = view_matrix * world_position;
+    clip_space = projection_matrix * position_relative_to_camera;
+    gl_Position = clip_space;
+    texture_coords = world_position.xz / WAVE_TILE_SIZE;
+    to_camera_vector = camera_position.xyz - world_position.xyz;
+    // the sun
+    from_light_vector = world_position.xyz - light_position[0].xyz;
+
+    visibility = fog_visibility(position_relative_to_camera);
+}
@@ -1,6 +1,8 @@
 //#![feature(associated_consts)]
 #[macro_use]
 extern crate imgui;
+#[macro_use]
+extern crate serde_derive;
 mod motorino;
 use motorino::Motorino;
 
@@ -0,0 +1,82 @@
+use super::gl::types::GLuint;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GpuResource {
+    VertexArray(GLuint),
+    Buffer(GLuint),
+    Texture(GLuint)
+}
+
+/// GL objects can only be deleted with the context current, so dropped assets queue their
+/// objects here and the loader deletes them on the main thread
+pub type ReleaseQueue = Arc<Mutex<Vec<GpuResource>>>;
+
+/// Owns a set of GL objects for as long as any component holds a handle to it
+pub struct GpuAsset {
+    resources: Vec<GpuResource>,
+    release_queue: ReleaseQueue
+}
+
+pub type AssetHandle = Arc<GpuAsset>;
+
+impl GpuAsset {
+    pub fn new(resources: Vec<GpuResource>, release_queue: &ReleaseQueue) -> AssetHandle {
+        Arc::new(GpuAsset { resources, release_queue: release_queue.clone() })
+    }
+}
+
+impl Drop for GpuAsset {
+    fn drop(&mut self) {
+        if let Ok(mut release_queue) = self.release_queue.lock() {
+            release_queue.extend(self.resources.drain(..));
+        }
+    }
+}
+
+impl fmt::Debug for GpuAsset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GpuAsset {:?}", self.resources)
+    }
+}
+
+/// Maps a resource path to what was loaded from it for as long as its asset is alive.
+/// Values are stored without their handle so the cache itself doesn't keep anything loaded.
+pub struct AssetCache<T> {
+    entries: HashMap<String, (T, Weak<GpuAsset>)>
+}
+
+impl<T: Clone> AssetCache<T> {
+    pub fn new() -> AssetCache<T> {
+        AssetCache { entries: HashMap::new() }
+    }
+
+    pub fn get(&self, path: &str) -> Option<(T, AssetHandle)> {
+        self.entries.get(path)
+            .and_then(|(value, asset)| asset.upgrade().map(|asset| (value.clone(), asset)))
+    }
+
+    pub fn insert(&mut self, path: &str, value: T, asset: &AssetHandle) {
+        self.entries.insert(path.to_owned(), (value, Arc::downgrade(asset)));
+    }
+
+    pub fn prune(&mut self) {
+        self.entries.retain(|_, &mut (_, ref asset)| asset.upgrade().is_some());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AssetStats {
+    pub textures: usize,
+    pub vertex_arrays: usize,
+    pub buffers: usize,
+    pub cached_textures: usize,
+    pub cached_models: usize,
+    pub released: usize
+}
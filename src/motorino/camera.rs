@@ -1,5 +1,5 @@
 
-use super::cgmath::{Point3, Vector3, Matrix4, vec3};
+use super::cgmath::{Deg, Point3, Vector3, Matrix4, perspective, vec3};
 use super::cgmath::prelude::*;
 use std::fmt;
 use super::specs::System;
@@ -7,16 +7,22 @@ use super::specs::ReadStorage;
 use super::specs::Write;
 use super::components::PlayerFlag;
 use super::components::Transform;
+use super::frustum::Frustum;
+use super::WindowSize;
 
 
 // Default camera values
 const YAW: f32 = -90.0;
 const PITCH: f32 = -10.0;
+const FOV: f32 = 45.0;
+const NEAR_PLANE: f32 = 0.1;
+const FAR_PLANE: f32 = 1500.0;
 
 type Point3f = Point3<f32>;
 type Vector3f = Vector3<f32>;
 type Matrix4f = Matrix4<f32>;
 
+#[derive(Clone)]
 pub struct Camera {
     pub follow_player: bool,
     // Camera Attributes
@@ -27,7 +33,11 @@ pub struct Camera {
     pub world_up: Vector3f,
     // Euler Angles
     pub yaw: f32,
-    pub pitch: f32
+    pub pitch: f32,
+    // Projection, the field of view is vertical and in degrees
+    pub fov: f32,
+    pub near_plane: f32,
+    pub far_plane: f32
 }
 
 impl Default for Camera {
@@ -40,7 +50,10 @@ impl Default for Camera {
             right: Vector3::zero(), // initialized later
             world_up: Vector3::unit_y(),
             yaw: YAW,
-            pitch: PITCH
+            pitch: PITCH,
+            fov: FOV,
+            near_plane: NEAR_PLANE,
+            far_plane: FAR_PLANE
         };
         camera.update_camera_vectors();
         camera
@@ -53,6 +66,14 @@ impl Camera {
         Matrix4::look_at(self.position, self.position + self.front, self.up)
     }
 
+    pub fn projection_matrix(&self, window_size: &WindowSize) -> Matrix4f {
+        perspective(Deg(self.fov), window_size.width as f32 / window_size.height as f32, self.near_plane, self.far_plane)
+    }
+
+    pub fn get_frustum(&self, projection_matrix: &Matrix4f) -> Frustum {
+        Frustum::from_matrix(&(*projection_matrix * self.get_view_matrix()))
+    }
+
     fn update_camera_vectors(&mut self) {
         // Calculate the new Front vector
         let front = Vector3 {
@@ -83,6 +104,14 @@ impl Camera {
         self.pitch = pitch;
         self.update_camera_vectors();
     }
+
+    /// The same camera looking up from under a horizontal mirror at `height`
+    pub fn mirrored_at(&self, height: f32) -> Camera {
+        let mut camera = self.clone();
+        camera.position.y = height - (self.position.y - height);
+        camera.set_pitch(-self.pitch);
+        camera
+    }
 }
 
 impl fmt::Display for Camera {
@@ -5,6 +5,7 @@ use super::specs::NullStorage;
 use super::terrain::Heights;
 use super::models::Loader;
 use super::gl::types::GLuint;
+use super::assets::AssetHandle;
 
 
 #[derive(Clone, Copy, Debug)]
@@ -39,6 +40,41 @@ impl Component for Velocity {
     type Storage = VecStorage<Self>;
 }
 
+/// A lamp carried by an entity, lighting its surroundings from its `Transform` position
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub colour: Vector3<f32>,
+    /// constant, linear and quadratic falloff with distance
+    pub attenuation: Vector3<f32>,
+    /// from the entity position, so the light can sit at the top of a lamp post
+    pub offset: Vector3<f32>
+}
+
+impl Component for PointLight {
+    type Storage = VecStorage<Self>;
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        PointLight {
+            colour: Vector3::new(1.0, 1.0, 1.0),
+            attenuation: Vector3::new(1.0, 0.01, 0.002),
+            offset: Vector3::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// A square of water centred on the entity's `Transform`, its surface at the transform height
+#[derive(Clone, Copy, Debug)]
+pub struct Water {
+    /// length of each side in world units
+    pub size: f32
+}
+
+impl Component for Water {
+    type Storage = VecStorage<Self>;
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct PlayerFlag;
 
@@ -53,6 +89,7 @@ impl Component for SkyboxFlag {
     type Storage = NullStorage<Self>;
 }
 
+/// The terrain chunk an entity belongs to, it is deleted along with the chunk
 #[derive(Clone, Copy, Debug, Default)]
 pub struct GridPosition {
     pub x: i32,
@@ -63,7 +100,7 @@ impl Component for GridPosition {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct TerrainTexturePack {
     pub background_texture: Texture,
     pub r_texture: Texture,
@@ -87,16 +124,19 @@ impl TerrainTexturePack {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct Texture {
-    pub texture_id: GLuint
+    pub texture_id: GLuint,
+    // only held so the texture lives as long as the component
+    #[allow(dead_code)]
+    pub asset: Option<AssetHandle>
 }
 
 impl Component for Texture {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct SkyboxTexture {
     pub day_texture: Texture,
     pub night_texture: Texture
@@ -106,11 +146,17 @@ impl Component for SkyboxTexture {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct SimpleTexture {
     pub texture_id: GLuint,
     pub reflectivity: f32,
-    pub shine_damper: f32}
+    pub shine_damper: f32,
+    pub diffuse_colour: Vector3<f32>,
+    // cut-out textures like foliage are drawn two sided
+    pub has_transparency: bool,
+    // light from straight above so flat quads don't go dark side on to the sun
+    pub use_fake_lighting: bool,
+    pub asset: Option<AssetHandle>}
 
 impl Component for SimpleTexture {
     type Storage = VecStorage<Self>;
@@ -121,26 +167,98 @@ impl Default for SimpleTexture {
         SimpleTexture {
             texture_id: 0,
             reflectivity: 0.0,
-            shine_damper: 0.0
+            shine_damper: 0.0,
+            diffuse_colour: Vector3::new(1.0, 1.0, 1.0),
+            has_transparency: false,
+            use_fake_lighting: false,
+            asset: None
         }
     }
 }
 
 #[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>
+}
+
+impl BoundingBox {
+    pub fn from_positions(positions: &[f32]) -> BoundingBox {
+        if positions.len() < 3 {
+            return BoundingBox::default();
+        }
+        let mut min = Vector3::new(positions[0], positions[1], positions[2]);
+        let mut max = min;
+        for position in positions.chunks(3) {
+            min.x = min.x.min(position[0]);
+            min.y = min.y.min(position[1]);
+            min.z = min.z.min(position[2]);
+            max.x = max.x.max(position[0]);
+            max.y = max.y.max(position[1]);
+            max.z = max.z.max(position[2]);
+        }
+        BoundingBox { min, max }
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn radius(&self) -> f32 {
+        use super::cgmath::InnerSpace;
+        ((self.max - self.min) * 0.5).magnitude()
+    }
+
+    pub fn translate(&self, offset: Vector3<f32>) -> BoundingBox {
+        BoundingBox { min: self.min + offset, max: self.max + offset }
+    }
+}
+
+impl Default for BoundingBox {
+    fn default() -> BoundingBox {
+        BoundingBox {
+            min: Vector3::new(0.0, 0.0, 0.0),
+            max: Vector3::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct RawModel {
     pub vao_id: GLuint,
-    pub vertex_count: usize
+    pub vertex_count: usize,
+    pub bounding_box: BoundingBox,
+    // only held so the buffers live as long as the component
+    #[allow(dead_code)]
+    pub asset: Option<AssetHandle>
 }
 
 impl Component for RawModel {
     type Storage = VecStorage<Self>;
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Debug)]
+pub struct ModelPart {
+    pub raw_model: RawModel,
+    pub texture: SimpleTexture
+}
+
+/// A model loaded from a file with several objects, each drawn with its own material
+#[derive(Clone, Debug)]
+pub struct MultiPartModel {
+    pub parts: Vec<ModelPart>
+}
+
+impl Component for MultiPartModel {
+    type Storage = VecStorage<Self>;
+}
+
+#[derive(Clone)]
 pub struct TerrainPhysics {
     pub heights: Heights,
     pub x: f32,
-    pub z: f32
+    pub z: f32,
+    pub size: f32
 }
 
 impl Component for TerrainPhysics {
@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::PathBuf;
+use super::camera::Camera;
+use super::cgmath::Vector3;
+use super::environment::Fog;
+use super::player::PlayerSettings;
+use super::toml;
+use super::toml::Value;
+use super::toml::value::Table;
+use super::{Motorino, WindowSize};
+
+/// Settings read at start-up from a TOML file, `motorino.toml` unless `--config <file>` names
+/// another, and then from `--<section>.<key>=<value>` arguments, e.g. `--camera.fov=60`.
+/// Anything left out keeps its default.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    /// relative to the executable, unless absolute
+    pub resource_directory: String,
+    pub window: WindowConfig,
+    pub camera: CameraConfig,
+    pub player: PlayerSettings,
+    pub fog: FogConfig,
+    // where it was loaded from, and is saved back to
+    #[serde(skip)]
+    path: PathBuf
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            resource_directory: "res-output".to_string(),
+            window: WindowConfig::default(),
+            camera: CameraConfig::default(),
+            player: PlayerSettings::default(),
+            fog: FogConfig::default(),
+            path: PathBuf::from(EngineConfig::DEFAULT_PATH)
+        }
+    }
+}
+
+impl EngineConfig {
+    const DEFAULT_PATH: &'static str = "motorino.toml";
+    pub const USAGE: &'static str = "Usage: motorino [--config <file>] [--<section>.<key>=<value> ...]";
+
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<EngineConfig, String> {
+        let mut path = PathBuf::from(EngineConfig::DEFAULT_PATH);
+        let mut overrides = Vec::new();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                path = PathBuf::from(args.next().ok_or("Missing file name after --config")?);
+            } else if arg.starts_with("--") && arg.contains('=') {
+                overrides.push(arg[2..].to_string());
+            } else {
+                return Err(format!("Unknown argument {}, expected --config <file> or --<section>.<key>=<value>", arg));
+            }
+        }
+
+        // a missing file is fine, everything is defaulted
+        let mut value = if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .map_err(|_| format!("Unable to read file {}", path.display()))?;
+            contents.parse::<Value>()
+                .map_err(|e| format!("Unable to parse {}: {}", path.display(), e))?
+        } else {
+            Value::Table(Table::new())
+        };
+        for config_override in overrides.iter() {
+            EngineConfig::apply_override(&mut value, config_override)?;
+        }
+        let mut config: EngineConfig = value.try_into()
+            .map_err(|e| format!("Invalid config: {}", e))?;
+        config.path = path;
+        Ok(config)
+    }
+
+    /// Takes in the values as they are now, which the debug ui may have changed
+    pub fn update(&mut self, camera: &Camera, fog: &Fog, player_settings: &PlayerSettings, window_size: &WindowSize) {
+        self.window = WindowConfig { width: window_size.window_width, height: window_size.window_height };
+        self.camera = CameraConfig::from_camera(camera);
+        self.fog = FogConfig::from_fog(fog);
+        self.player = *player_settings;
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let contents = toml::to_string_pretty(self)
+            .map_err(|e| format!("Unable to write config: {}", e))?;
+        fs::write(&self.path, contents)
+            .map_err(|_| format!("Unable to write file {}", self.path.display()))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    // `section.key=value`, the value read as TOML so numbers and booleans keep their type
+    fn apply_override(value: &mut Value, config_override: &str) -> Result<(), String> {
+        let mut parts = config_override.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let raw_value = parts.next().unwrap_or("");
+        let new_value = format!("value = {}", raw_value).parse::<Value>().ok()
+            .and_then(|table| table.get("value").cloned())
+            .unwrap_or_else(|| Value::String(raw_value.to_string()));
+
+        let keys: Vec<&str> = key.split('.').collect();
+        let (last_key, sections) = keys.split_last().ok_or("Empty config key")?;
+        let mut table = value;
+        for section in sections {
+            table = table.as_table_mut()
+                .ok_or(format!("{} is not a config section", key))?
+                .entry(section.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+        }
+        table.as_table_mut()
+            .ok_or(format!("{} is not a config section", key))?
+            .insert(last_key.to_string(), new_value);
+        Ok(())
+    }
+}
+
+/// In screen coordinates
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig { width: Motorino::WIDTH, height: Motorino::HEIGHT }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub fov: f32,
+    pub near_plane: f32,
+    pub far_plane: f32
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig::from_camera(&Camera::default())
+    }
+}
+
+impl CameraConfig {
+    fn from_camera(camera: &Camera) -> CameraConfig {
+        CameraConfig { fov: camera.fov, near_plane: camera.near_plane, far_plane: camera.far_plane }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.fov = self.fov;
+        camera.near_plane = self.near_plane;
+        camera.far_plane = self.far_plane;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FogConfig {
+    pub day_colour: [f32; 3],
+    pub night_colour: [f32; 3],
+    pub density: f32,
+    pub gradient: f32
+}
+
+impl Default for FogConfig {
+    fn default() -> Self {
+        FogConfig::from_fog(&Fog::default())
+    }
+}
+
+impl FogConfig {
+    fn from_fog(fog: &Fog) -> FogConfig {
+        FogConfig {
+            day_colour: fog.day_colour.into(),
+            night_colour: fog.night_colour.into(),
+            density: fog.density,
+            gradient: fog.gradient
+        }
+    }
+
+    pub fn to_fog(self) -> Fog {
+        Fog {
+            day_colour: Vector3::from(self.day_colour),
+            night_colour: Vector3::from(self.night_colour),
+            density: self.density,
+            gradient: self.gradient
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter()
+    }
+
+    // a file of its own per test, as they run in parallel
+    fn config_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("motorino-config-test-{}.toml", name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn missing_file() -> String {
+        env::temp_dir().join("motorino-config-test-missing.toml").to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn a_missing_file_gives_the_defaults() {
+        let config = EngineConfig::from_args(args(&["--config", &missing_file()])).unwrap();
+        assert_eq!(config.resource_directory, "res-output");
+        assert_eq!(config.window.width, Motorino::WIDTH);
+        assert_eq!(config.camera.fov, Camera::default().fov);
+    }
+
+    #[test]
+    fn the_file_fills_in_what_it_has() {
+        let path = config_file("file", "resource_directory = \"assets\"\n[camera]\nfov = 60.0\n");
+        let config = EngineConfig::from_args(args(&["--config", &path])).unwrap();
+        assert_eq!(config.resource_directory, "assets");
+        assert_eq!(config.camera.fov, 60.0);
+        assert_eq!(config.camera.far_plane, Camera::default().far_plane);
+        assert_eq!(config.path(), &PathBuf::from(path));
+    }
+
+    #[test]
+    fn overrides_take_precedence_over_the_file() {
+        let path = config_file("overrides", "[camera]\nfov = 60.0\n");
+        let config = EngineConfig::from_args(args(&["--config", &path, "--camera.fov=75", "--window.width=640"])).unwrap();
+        assert_eq!(config.camera.fov, 75.0);
+        assert_eq!(config.window.width, 640);
+    }
+
+    #[test]
+    fn override_values_that_are_not_toml_are_strings() {
+        let config = EngineConfig::from_args(args(&["--config", &missing_file(), "--resource_directory=my-res"])).unwrap();
+        assert_eq!(config.resource_directory, "my-res");
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert!(EngineConfig::from_args(args(&["--config"])).is_err());
+        assert!(EngineConfig::from_args(args(&["fov=60"])).is_err());
+        assert!(EngineConfig::from_args(args(&["--config", &missing_file(), "--camera.fov=wide"])).is_err());
+        assert!(EngineConfig::from_args(args(&["--config", &missing_file(), "--resource_directory.x=1"])).is_err());
+    }
+
+    #[test]
+    fn a_saved_config_loads_back_the_same() {
+        let path = config_file("saved", "");
+        let mut config = EngineConfig::from_args(args(&["--config", &path])).unwrap();
+        config.player.run_speed = 42.0;
+        config.fog.density = 0.5;
+        config.save().unwrap();
+        let loaded = EngineConfig::from_args(args(&["--config", &path])).unwrap();
+        assert_eq!(loaded.player.run_speed, 42.0);
+        assert_eq!(loaded.fog.density, 0.5);
+    }
+}
@@ -0,0 +1,297 @@
+use std::f32::consts::PI;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use super::cgmath::{Matrix4, SquareMatrix, Vector3, Vector4};
+use super::components::{BoundingBox, MultiPartModel, PlayerFlag, PointLight, RawModel, Transform, Velocity};
+use super::debugui::DebugInfo;
+use super::frameuniforms::FrameUniformUploader;
+use super::gl;
+use super::gl::types::*;
+use super::renderers::{EntityRenderer, RenderSettings, RenderTarget};
+use super::resources::ResourceLoader;
+use super::shaders::ShaderProgram;
+use super::shadows::ShadowMap;
+use super::specs::{Read, ReadStorage, System, Write};
+use super::WindowSize;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DebugDrawSettings {
+    pub enabled: bool,
+    pub bounding_boxes: bool,
+    pub lights: bool,
+    pub player_velocity: bool,
+    /// the volume the shadow map covers
+    pub shadow_frustum: bool
+}
+
+impl Default for DebugDrawSettings {
+    fn default() -> Self {
+        DebugDrawSettings {
+            enabled: false,
+            bounding_boxes: true,
+            lights: true,
+            player_velocity: true,
+            shadow_frustum: true
+        }
+    }
+}
+
+/// A label drawn by the debug ui at a world position
+#[derive(Clone, Debug)]
+pub struct DebugText {
+    pub position: Vector3<f32>,
+    pub text: String,
+    pub colour: Vector3<f32>
+}
+
+/// Lines and labels any system can add to during a frame. They are drawn over the scene by
+/// `DebugDrawRenderer` and the debug ui, then cleared at the end of the frame.
+#[derive(Default)]
+pub struct DebugDraw {
+    // position then colour for both ends of every line
+    line_data: Vec<f32>,
+    texts: Vec<DebugText>
+}
+
+impl DebugDraw {
+    const VERTEX_LENGTH: usize = 6;
+    const CIRCLE_SEGMENTS: usize = 24;
+
+    pub fn line(&mut self, from: Vector3<f32>, to: Vector3<f32>, colour: Vector3<f32>) {
+        self.line_data.extend_from_slice(&[from.x, from.y, from.z, colour.x, colour.y, colour.z,
+                                           to.x, to.y, to.z, colour.x, colour.y, colour.z]);
+    }
+
+    #[allow(dead_code)]
+    pub fn aabb(&mut self, bounding_box: &BoundingBox, colour: Vector3<f32>) {
+        self.oriented_box(bounding_box, &Matrix4::identity(), colour);
+    }
+
+    /// A model space bounding box as it sits in the world after `transformation_matrix`
+    pub fn oriented_box(&mut self, bounding_box: &BoundingBox, transformation_matrix: &Matrix4<f32>, colour: Vector3<f32>) {
+        let (min, max) = (bounding_box.min, bounding_box.max);
+        // bit 0 picks max x, bit 1 max y and bit 2 max z
+        let corners: Vec<Vector3<f32>> = (0..8)
+            .map(|i| Vector3::new(if i & 1 == 0 { min.x } else { max.x },
+                                  if i & 2 == 0 { min.y } else { max.y },
+                                  if i & 4 == 0 { min.z } else { max.z }))
+            .map(|corner| (*transformation_matrix * corner.extend(1.0)).truncate())
+            .collect();
+        self.box_edges(&corners, colour);
+    }
+
+    /// The edges of the volume a projection * view matrix sees
+    pub fn frustum(&mut self, projection_view: &Matrix4<f32>, colour: Vector3<f32>) {
+        let inverse = match projection_view.invert() {
+            Some(inverse) => inverse,
+            None => return
+        };
+        let corners: Vec<Vector3<f32>> = (0..8)
+            .map(|i| Vector4::new(if i & 1 == 0 { -1.0 } else { 1.0 },
+                                  if i & 2 == 0 { -1.0 } else { 1.0 },
+                                  if i & 4 == 0 { -1.0 } else { 1.0 },
+                                  1.0))
+            .map(|corner| {
+                let world = inverse * corner;
+                world.truncate() / world.w
+            })
+            .collect();
+        self.box_edges(&corners, colour);
+    }
+
+    /// Three circles, one around each axis
+    pub fn sphere(&mut self, center: Vector3<f32>, radius: f32, colour: Vector3<f32>) {
+        let point = |angle: f32, axis: usize| {
+            let (sin, cos) = (angle.sin() * radius, angle.cos() * radius);
+            center + match axis {
+                0 => Vector3::new(0.0, sin, cos),
+                1 => Vector3::new(sin, 0.0, cos),
+                _ => Vector3::new(sin, cos, 0.0)
+            }
+        };
+        for axis in 0..3 {
+            for segment in 0..DebugDraw::CIRCLE_SEGMENTS {
+                let from = segment as f32 / DebugDraw::CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+                let to = (segment + 1) as f32 / DebugDraw::CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+                self.line(point(from, axis), point(to, axis), colour);
+            }
+        }
+    }
+
+    /// x, y and z in red, green and blue
+    pub fn axes(&mut self, position: Vector3<f32>, size: f32) {
+        self.line(position, position + Vector3::unit_x() * size, Vector3::unit_x());
+        self.line(position, position + Vector3::unit_y() * size, Vector3::unit_y());
+        self.line(position, position + Vector3::unit_z() * size, Vector3::unit_z());
+    }
+
+    pub fn text(&mut self, position: Vector3<f32>, text: String, colour: Vector3<f32>) {
+        self.texts.push(DebugText { position, text, colour });
+    }
+
+    pub fn texts(&self) -> &[DebugText] {
+        &self.texts
+    }
+
+    pub fn clear(&mut self) {
+        self.line_data.clear();
+        self.texts.clear();
+    }
+
+    fn box_edges(&mut self, corners: &[Vector3<f32>], colour: Vector3<f32>) {
+        // corners one bit apart share an edge
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], colour);
+                }
+            }
+        }
+    }
+
+    fn vertex_count(&self) -> usize {
+        self.line_data.len() / DebugDraw::VERTEX_LENGTH
+    }
+}
+
+/// Adds the bounding volumes, lights, player velocity and shadow volume to `DebugDraw`,
+/// as chosen in `DebugDrawSettings`
+pub struct SceneDebugDrawer;
+
+impl<'a> System<'a> for SceneDebugDrawer {
+    type SystemData = (ReadStorage<'a, Transform>,
+                       ReadStorage<'a, RawModel>,
+                       ReadStorage<'a, MultiPartModel>,
+                       ReadStorage<'a, PointLight>,
+                       ReadStorage<'a, Velocity>,
+                       ReadStorage<'a, PlayerFlag>,
+                       Read<'a, ShadowMap>,
+                       Read<'a, RenderSettings>,
+                       Write<'a, DebugDraw>);
+
+    fn run(&mut self, (transform, model, multi_part_model, point_light, velocity, player_flag, shadow_map, render_settings, mut debug_draw): Self::SystemData) {
+        let settings = &render_settings.debug_draw;
+        if !settings.enabled {
+            return;
+        }
+
+        use super::specs::Join;
+        if settings.bounding_boxes {
+            let colour = Vector3::new(1.0, 1.0, 0.0);
+            for (transform, model) in (&transform, &model).join() {
+                debug_draw.oriented_box(&model.bounding_box, &EntityRenderer::transformation_matrix(transform), colour);
+            }
+            for (transform, multi_part_model) in (&transform, &multi_part_model).join() {
+                let transformation_matrix = EntityRenderer::transformation_matrix(transform);
+                for part in multi_part_model.parts.iter() {
+                    debug_draw.oriented_box(&part.raw_model.bounding_box, &transformation_matrix, colour);
+                }
+            }
+        }
+
+        if settings.lights {
+            for (transform, point_light) in (&transform, &point_light).join() {
+                let position = transform.position + point_light.offset;
+                debug_draw.sphere(position, 1.0, point_light.colour);
+                debug_draw.text(position, format!("light ({:.1}, {:.1}, {:.1})", point_light.colour.x, point_light.colour.y, point_light.colour.z), point_light.colour);
+            }
+        }
+
+        if settings.player_velocity {
+            for (transform, velocity, _player_flag) in (&transform, &velocity, &player_flag).join() {
+                // the same heading the player controller moves along
+                let heading = transform.rotation.y.to_radians();
+                let direction = Vector3::new(velocity.run_velocity * heading.sin(), velocity.upwards_velocity, velocity.run_velocity * heading.cos());
+                let position = transform.position + Vector3::new(0.0, 1.0, 0.0);
+                debug_draw.axes(position, 2.0);
+                // as far as the player gets in a fifth of a second
+                debug_draw.line(position, position + direction * 0.2, Vector3::new(1.0, 0.0, 1.0));
+            }
+        }
+
+        if settings.shadow_frustum {
+            debug_draw.frustum(&shadow_map.light_space_matrix, Vector3::new(1.0, 0.5, 0.0));
+        }
+    }
+}
+
+/// Draws the lines in `DebugDraw` on top of everything else in the frame
+pub struct DebugDrawRenderer {
+    line_shader: ShaderProgram,
+    vao: GLuint,
+    vbo: GLuint
+}
+
+impl<'a> System<'a> for DebugDrawRenderer {
+    type SystemData = (Read<'a, DebugDraw>,
+                       Read<'a, WindowSize>,
+                       Write<'a, DebugInfo>);
+
+    fn run(&mut self, (debug_draw, window_size, mut debug_info): Self::SystemData) {
+        let vertex_count = debug_draw.vertex_count();
+        if vertex_count == 0 {
+            return;
+        }
+        RenderTarget::Screen.bind(&window_size);
+        // drawn with the camera from the `FrameUniforms` block
+        self.line_shader.start();
+        self.store_line_data(&debug_draw.line_data);
+        debug_info.current_frame_draw_calls += 1;
+        unsafe {
+            gl::Disable(gl::DEPTH_TEST);
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::LINES, 0, vertex_count as i32);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        self.line_shader.stop();
+    }
+}
+
+impl DebugDrawRenderer {
+
+    const VERTEX_SHADER: &'static str = "shaders/debug-lines.vert";
+    const FRAGMENT_SHADER: &'static str = "shaders/debug-lines.frag";
+
+    pub fn new(resource_loader: &ResourceLoader) -> Result<DebugDrawRenderer, String> {
+        let line_shader = FrameUniformUploader::shader_program(resource_loader, DebugDrawRenderer::VERTEX_SHADER, DebugDrawRenderer::FRAGMENT_SHADER)
+            .map_err(|e| e.to_string())?;
+        let mut vao = 0;
+        let mut vbo = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+            gl::GenBuffers(1, &mut vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+            let stride = (DebugDraw::VERTEX_LENGTH * mem::size_of::<GLfloat>()) as GLsizei;
+            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null()); // positions
+            gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, (3 * mem::size_of::<GLfloat>()) as *const c_void); // colours
+            gl::EnableVertexAttribArray(0);
+            gl::EnableVertexAttribArray(1);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+        Ok(DebugDrawRenderer { line_shader, vao, vbo })
+    }
+
+    fn store_line_data(&self, line_data: &[f32]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           mem::size_of_val(line_data) as GLsizeiptr,
+                           &line_data[0] as *const f32 as *const c_void,
+                           gl::STREAM_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+}
+
+impl Drop for DebugDrawRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo);
+        }
+    }
+}
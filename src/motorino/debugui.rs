@@ -1,13 +1,13 @@
 use super::specs::{System, Write, Read};
 use super::UpdateDeltaTime;
 use super::camera::Camera;
-use super::environment::{Light, Fog};
-use super::imgui::{ImGui, ImGuiCond, FrameSize};
+use super::environment::{Light, Fog, TimeOfDay};
+use super::imgui::{ImGui, ImGuiCol, ImGuiCond, FrameSize};
 use super::imgui_opengl_renderer::Renderer as ImguiRenderer;
 use super::glfw::{Action, Key};
 use super::WindowSize;
 use super::CursorPosition;
-use super::renderers::RenderSettings;
+use super::renderers::{RenderMode, RenderSettings};
 use super::input::KeyEvents;
 use super::input::MouseState;
 use super::ringbuffer::RingBuffer;
@@ -15,13 +15,25 @@ use super::specs::ReadStorage;
 use super::specs::WriteStorage;
 use super::components::PlayerFlag;
 use super::components::Transform;
+use super::assets::AssetStats;
+use super::shadows::ShadowSettings;
+use super::debugdraw::DebugDraw;
+use super::config::EngineConfig;
+use super::player::PlayerSettings;
+use super::shaders::ShaderReloadStatus;
+use super::cgmath::{Matrix4, Vector3};
 
 pub struct DebugInfo {
     pub frame_times: RingBuffer<f32>,
     pub triangle_counts: RingBuffer<i32>,
     pub draw_calls: RingBuffer<i32>,
     pub current_frame_triangle_count: i32,
-    pub current_frame_draw_calls: i32
+    pub current_frame_draw_calls: i32,
+    pub current_frame_drawn_count: i32,
+    pub current_frame_culled_count: i32,
+    pub asset_stats: AssetStats,
+    /// the last frame buffer that couldn't be made at the window's new size
+    pub framebuffer_error: Option<String>
 }
 
 impl Default for DebugInfo {
@@ -31,11 +43,34 @@ impl Default for DebugInfo {
             triangle_counts: RingBuffer::<i32>::new(50),
             draw_calls: RingBuffer::<i32>::new(50),
             current_frame_triangle_count: 0,
-            current_frame_draw_calls: 0
+            current_frame_draw_calls: 0,
+            current_frame_drawn_count: 0,
+            current_frame_culled_count: 0,
+            asset_stats: AssetStats::default(),
+            framebuffer_error: None
         }
     }
 }
 
+impl DebugInfo {
+    pub fn add(&mut self, stats: &DrawStats) {
+        self.current_frame_triangle_count += stats.triangle_count;
+        self.current_frame_draw_calls += stats.draw_calls;
+        self.current_frame_drawn_count += stats.drawn_count;
+        self.current_frame_culled_count += stats.culled_count;
+    }
+}
+
+/// What a renderer drew in one pass. The scene renderers run for every water pass as well as the
+/// frame, so only add their stats to `DebugInfo` for the passes that count.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawStats {
+    pub triangle_count: i32,
+    pub draw_calls: i32,
+    pub drawn_count: i32,
+    pub culled_count: i32
+}
+
 pub struct DebugInfoResetter;
 
 impl<'a> System<'a> for DebugInfoResetter {
@@ -49,9 +84,12 @@ impl<'a> System<'a> for DebugInfoResetter {
         debug_info.frame_times.push(dt.0);
         debug_info.current_frame_triangle_count = 0;
         debug_info.current_frame_draw_calls = 0;
+        debug_info.current_frame_drawn_count = 0;
+        debug_info.current_frame_culled_count = 0;
     }
 }
 
+#[derive(Default)]
 pub struct DebugUi {
     pub imgui: Option<ImGui>,
     pub imgui_renderer: Option<ImguiRenderer>
@@ -75,104 +113,96 @@ impl DebugUi {
 
     pub fn setup_keyboard_input_keys(&mut self) {
         use imgui::ImGuiKey;
-        match &mut self.imgui {
-            Some(imgui) => {
-                imgui.set_imgui_key(ImGuiKey::Tab, 0);
-                imgui.set_imgui_key(ImGuiKey::LeftArrow, 1);
-                imgui.set_imgui_key(ImGuiKey::RightArrow, 2);
-                imgui.set_imgui_key(ImGuiKey::UpArrow, 3);
-                imgui.set_imgui_key(ImGuiKey::DownArrow, 4);
-                imgui.set_imgui_key(ImGuiKey::PageUp, 5);
-                imgui.set_imgui_key(ImGuiKey::PageDown, 6);
-                imgui.set_imgui_key(ImGuiKey::Home, 7);
-                imgui.set_imgui_key(ImGuiKey::End, 8);
-                imgui.set_imgui_key(ImGuiKey::Delete, 9);
-                imgui.set_imgui_key(ImGuiKey::Backspace, 10);
-                imgui.set_imgui_key(ImGuiKey::Enter, 11);
-                imgui.set_imgui_key(ImGuiKey::Escape, 12);
-                imgui.set_imgui_key(ImGuiKey::A, 13);
-                imgui.set_imgui_key(ImGuiKey::C, 14);
-                imgui.set_imgui_key(ImGuiKey::V, 15);
-                imgui.set_imgui_key(ImGuiKey::X, 16);
-                imgui.set_imgui_key(ImGuiKey::Y, 17);
-                imgui.set_imgui_key(ImGuiKey::Z, 18);
-            },
-            None => {}
+        if let Some(imgui) = &mut self.imgui {
+            imgui.set_imgui_key(ImGuiKey::Tab, 0);
+            imgui.set_imgui_key(ImGuiKey::LeftArrow, 1);
+            imgui.set_imgui_key(ImGuiKey::RightArrow, 2);
+            imgui.set_imgui_key(ImGuiKey::UpArrow, 3);
+            imgui.set_imgui_key(ImGuiKey::DownArrow, 4);
+            imgui.set_imgui_key(ImGuiKey::PageUp, 5);
+            imgui.set_imgui_key(ImGuiKey::PageDown, 6);
+            imgui.set_imgui_key(ImGuiKey::Home, 7);
+            imgui.set_imgui_key(ImGuiKey::End, 8);
+            imgui.set_imgui_key(ImGuiKey::Delete, 9);
+            imgui.set_imgui_key(ImGuiKey::Backspace, 10);
+            imgui.set_imgui_key(ImGuiKey::Enter, 11);
+            imgui.set_imgui_key(ImGuiKey::Escape, 12);
+            imgui.set_imgui_key(ImGuiKey::A, 13);
+            imgui.set_imgui_key(ImGuiKey::C, 14);
+            imgui.set_imgui_key(ImGuiKey::V, 15);
+            imgui.set_imgui_key(ImGuiKey::X, 16);
+            imgui.set_imgui_key(ImGuiKey::Y, 17);
+            imgui.set_imgui_key(ImGuiKey::Z, 18);
         }
     }
 
     pub fn set_keyboard_input_state(&mut self, key_events: &KeyEvents) {
-        match &mut self.imgui {
-            Some(imgui) => {
-                for key_event in key_events.iter() {
-                    let pressed = key_event.action == Action::Press;
-                    match key_event.source {
-                        Key::Tab =>  imgui.set_key(0, pressed),
-                        Key::Left =>  imgui.set_key(1, pressed),
-                        Key::Right =>  imgui.set_key(2, pressed),
-                        Key::Up =>  imgui.set_key(3, pressed),
-                        Key::Down =>  imgui.set_key(4, pressed),
-                        Key::PageUp =>  imgui.set_key(5, pressed),
-                        Key::PageDown =>  imgui.set_key(6, pressed),
-                        Key::Home =>  imgui.set_key(7, pressed),
-                        Key::End =>  imgui.set_key(8, pressed),
-                        Key::Delete =>  imgui.set_key(9, pressed),
-                        Key::Backspace =>  imgui.set_key(10, pressed),
-                        Key::Enter =>  imgui.set_key(11, pressed),
-                        Key::Escape =>  imgui.set_key(12, pressed),
-                        Key::A =>  imgui.set_key(13, pressed),
-                        Key::C =>  imgui.set_key(14, pressed),
-                        Key::V =>  imgui.set_key(15, pressed),
-                        Key::X =>  imgui.set_key(16, pressed),
-                        Key::Y =>  imgui.set_key(17, pressed),
-                        Key::Z =>  imgui.set_key(18, pressed),
-                        Key::LeftControl | Key::RightControl => {
-                            imgui.set_key_ctrl(pressed)
-                        }
-                        Key::LeftShift | Key::RightShift =>  imgui.set_key_shift(pressed),
-                        Key::LeftAlt | Key::RightAlt =>  imgui.set_key_alt(pressed),
-                        Key::LeftSuper | Key::RightSuper =>  imgui.set_key_super(pressed),
-                        _ => {}
+        if let Some(imgui) = &mut self.imgui {
+            for key_event in key_events.iter() {
+                let pressed = key_event.action == Action::Press;
+                match key_event.source {
+                    Key::Tab =>  imgui.set_key(0, pressed),
+                    Key::Left =>  imgui.set_key(1, pressed),
+                    Key::Right =>  imgui.set_key(2, pressed),
+                    Key::Up =>  imgui.set_key(3, pressed),
+                    Key::Down =>  imgui.set_key(4, pressed),
+                    Key::PageUp =>  imgui.set_key(5, pressed),
+                    Key::PageDown =>  imgui.set_key(6, pressed),
+                    Key::Home =>  imgui.set_key(7, pressed),
+                    Key::End =>  imgui.set_key(8, pressed),
+                    Key::Delete =>  imgui.set_key(9, pressed),
+                    Key::Backspace =>  imgui.set_key(10, pressed),
+                    Key::Enter =>  imgui.set_key(11, pressed),
+                    Key::Escape =>  imgui.set_key(12, pressed),
+                    Key::A =>  imgui.set_key(13, pressed),
+                    Key::C =>  imgui.set_key(14, pressed),
+                    Key::V =>  imgui.set_key(15, pressed),
+                    Key::X =>  imgui.set_key(16, pressed),
+                    Key::Y =>  imgui.set_key(17, pressed),
+                    Key::Z =>  imgui.set_key(18, pressed),
+                    Key::LeftControl | Key::RightControl => {
+                        imgui.set_key_ctrl(pressed)
                     }
+                    Key::LeftShift | Key::RightShift =>  imgui.set_key_shift(pressed),
+                    Key::LeftAlt | Key::RightAlt =>  imgui.set_key_alt(pressed),
+                    Key::LeftSuper | Key::RightSuper =>  imgui.set_key_super(pressed),
+                    _ => {}
                 }
-            },
-            None => {}
+            }
         }
     }
 
     pub fn set_mouse_state(&mut self, cursor_position: &CursorPosition, mouse_state: &MouseState) {
 
-        match &mut self.imgui {
-            Some(imgui) => {
-                imgui.set_mouse_pos(cursor_position.x, cursor_position.y);
-                imgui.set_mouse_down([
-                    mouse_state.button1,
-                    mouse_state.button2,
-                    mouse_state.button3,
-                    false,
-                    false
-                ]);
-            },
-            None => {}
+        if let Some(imgui) = &mut self.imgui {
+            imgui.set_mouse_pos(cursor_position.x, cursor_position.y);
+            imgui.set_mouse_down([
+                mouse_state.button1,
+                mouse_state.button2,
+                mouse_state.button3,
+                false,
+                false
+            ]);
         }
     }
 }
 
-impl Default for DebugUi {
-    fn default() -> Self {
-        DebugUi{imgui: None, imgui_renderer: None}
-    }
-}
 
 unsafe impl Sync for DebugUi {
 }
 
-pub struct DebugUiBuilder;
+#[derive(Default)]
+pub struct DebugUiBuilder {
+    // what the last press of "Save config" did, shown under the button
+    config_save_status: Option<Result<String, String>>
+}
 
 impl<'a> System<'a> for DebugUiBuilder {
     type SystemData = (Write<'a, Camera>,
                        Write<'a, Light>,
                        Write<'a, Fog>, 
+                       Write<'a, TimeOfDay>,
+                       Write<'a, ShadowSettings>,
                        Write<'a, DebugUi>,
                        Read<'a, KeyEvents>,
                        Read<'a, MouseState>,
@@ -180,13 +210,19 @@ impl<'a> System<'a> for DebugUiBuilder {
                        Read<'a, DebugInfo>,
                        Read<'a, WindowSize>,
                        Read<'a, CursorPosition>,
-                       Read<'a, RenderSettings>,
+                       Write<'a, RenderSettings>,
+                       Read<'a, DebugDraw>,
+                       Write<'a, PlayerSettings>,
+                       Write<'a, EngineConfig>,
+                       Read<'a, ShaderReloadStatus>,
                        ReadStorage<'a, PlayerFlag>,
                        WriteStorage<'a, Transform>);
 
     fn run(&mut self, (mut camera,
         mut light,
         mut fog,
+        mut time_of_day,
+        mut shadow_settings,
         mut debug_ui,
         input_events,
         mouse_state,
@@ -194,7 +230,11 @@ impl<'a> System<'a> for DebugUiBuilder {
         debug_info,
         window_size,
         cursor_position,
-        render_settings,
+        mut render_settings,
+        debug_draw,
+        mut player_settings,
+        mut engine_config,
+        shader_reload_status,
         player_flag,
         mut transform): Self::SystemData) {
 
@@ -212,8 +252,32 @@ impl<'a> System<'a> for DebugUiBuilder {
         let imgui = imgui_opt.as_mut().unwrap();
         let imgui_renderer = imgui_renderer_opt.as_ref().unwrap();
 
+        let config_save_status = &mut self.config_save_status;
         let dt = dt.0;
-        let ui = imgui.frame(FrameSize::new(window_size.width as f64, window_size.height as f64, 1.0), dt);
+        let ui = imgui.frame(FrameSize::new(window_size.window_width as f64, window_size.window_height as f64, window_size.hidpi_factor()), dt);
+        if !debug_draw.texts().is_empty() {
+            let view_projection = camera.projection_matrix(&window_size) * camera.get_view_matrix();
+            // a transparent window over the whole screen that ignores the mouse, just to draw into
+            ui.with_color_var(ImGuiCol::WindowBg, [0.0, 0.0, 0.0, 0.0], || {
+                ui.window(im_str!("Debug labels"))
+                    .position((0.0, 0.0), ImGuiCond::Always)
+                    .size((window_size.window_width as f32, window_size.window_height as f32), ImGuiCond::Always)
+                    .title_bar(false)
+                    .resizable(false)
+                    .movable(false)
+                    .scroll_bar(false)
+                    .inputs(false)
+                    .build(|| {
+                        let draw_list = ui.get_window_draw_list();
+                        for debug_text in debug_draw.texts() {
+                            if let Some(screen_position) = DebugUiBuilder::screen_position(&view_projection, debug_text.position, &window_size) {
+                                let colour = [debug_text.colour.x, debug_text.colour.y, debug_text.colour.z, 1.0];
+                                draw_list.add_text(screen_position, colour, &debug_text.text);
+                            }
+                        }
+                    });
+            });
+        }
         ui.window(im_str!("Debug info"))
             .position((10.0, 10.0), ImGuiCond::FirstUseEver)
             .size((300.0, 300.0), ImGuiCond::FirstUseEver)
@@ -223,13 +287,46 @@ impl<'a> System<'a> for DebugUiBuilder {
                 ui.plot_lines(im_str!("Fps"), &fps_times).scale_min(0.0).scale_max(60.0).build();
                 ui.text(im_str!("Triangles: {:.1}", debug_info.current_frame_triangle_count));
                 ui.text(im_str!("Draw calls: {:.1}", debug_info.current_frame_draw_calls));
+                ui.text(im_str!("Drawn / culled: {} / {}", debug_info.current_frame_drawn_count, debug_info.current_frame_culled_count));
                 ui.text(im_str!("Smoothed {:.1}", debug_info.frame_times.deque().len() as f32/sum_frame_time));
                 ui.text(im_str!("Raw Mouse Position: ({:.1},{:.1})", cursor_position.x ,cursor_position.y));
+                ui.text(im_str!("Render mode: {:?} (F1-F6)", render_settings.render_mode));
+                // failed reloads stay on show until the shader is fixed, the last working version is drawn meanwhile
+                for error in shader_reload_status.errors.iter() {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], im_str!("Shader {} failed to reload", error.program));
+                    ui.text_wrapped(im_str!("{}", error.log));
+                }
+                if let Some(ref message) = debug_info.framebuffer_error {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], im_str!("Frame buffer error"));
+                    ui.text_wrapped(im_str!("{}", message));
+                }
+                if ui.collapsing_header(im_str!("Shaders")).build() {
+                    ui.text(im_str!("Reloaded: {}", shader_reload_status.reloaded));
+                    if let Some(ref program) = shader_reload_status.last_reloaded {
+                        ui.text(im_str!("Last reloaded: {}", program));
+                    }
+                    ui.text(im_str!("Failing: {}", shader_reload_status.errors.len()));
+                    for error in shader_reload_status.uniform_errors.iter() {
+                        ui.text_colored([1.0, 0.8, 0.3, 1.0], im_str!("{}: {}", error.program, error.log));
+                    }
+                }
+                if ui.collapsing_header(im_str!("Assets")).build() {
+                    let asset_stats = &debug_info.asset_stats;
+                    ui.text(im_str!("Textures: {}", asset_stats.textures));
+                    ui.text(im_str!("Vertex arrays: {}", asset_stats.vertex_arrays));
+                    ui.text(im_str!("Buffers: {}", asset_stats.buffers));
+                    ui.text(im_str!("Cached textures / models: {} / {}", asset_stats.cached_textures, asset_stats.cached_models));
+                    ui.text(im_str!("Released: {}", asset_stats.released));
+                }
                 if ui.collapsing_header(im_str!("Player")).build() {
                     use super::specs::Join;
-                    for (_player_flag, mut transform) in (&player_flag, &mut transform).join() {
+                    for (_player_flag, transform) in (&player_flag, &mut transform).join() {
                         ui.drag_float3(im_str!("Player position"), transform.position.as_mut()).build();
                     }
+                    ui.slider_float(im_str!("run speed"), &mut player_settings.run_speed, 0.0, 200.0).build();
+                    ui.slider_float(im_str!("turn speed"), &mut player_settings.turn_speed, 0.0, 360.0).build();
+                    ui.slider_float(im_str!("gravity"), &mut player_settings.gravity, -200.0, 0.0).build();
+                    ui.slider_float(im_str!("jump power"), &mut player_settings.jump_power, 0.0, 100.0).build();
                 }
                 if ui.collapsing_header(im_str!("Camera")).build() {
                     ui.checkbox(im_str!("Follow player"), &mut camera.follow_player);
@@ -239,21 +336,87 @@ impl<'a> System<'a> for DebugUiBuilder {
                         camera.set_yaw(yaw_pitch[0]);
                         camera.set_pitch(yaw_pitch[1]);
                     }
+                    ui.slider_float(im_str!("field of view"), &mut camera.fov, 20.0, 120.0).build();
+                    ui.slider_float(im_str!("near plane"), &mut camera.near_plane, 0.01, 10.0).build();
+                    ui.slider_float(im_str!("far plane"), &mut camera.far_plane, 100.0, 5000.0).build();
+                }
+                if ui.collapsing_header(im_str!("Time of day")).build() {
+                    ui.slider_float(im_str!("hour"), &mut time_of_day.hour, 0.0, 24.0).build();
+                    ui.slider_float(im_str!("day length (s)"), &mut time_of_day.day_length, 10.0, 1200.0).build();
+                    ui.checkbox(im_str!("Paused"), &mut time_of_day.paused);
+                    ui.text(im_str!("Sun position: ({:.1},{:.1},{:.1})", light.position.x, light.position.y, light.position.z));
+                    ui.text(im_str!("Sun colour: ({:.2},{:.2},{:.2})", light.colour.x, light.colour.y, light.colour.z));
                 }
                 if ui.collapsing_header(im_str!("Light")).build() {
-                    ui.drag_float3(im_str!("Light position"), light.position.as_mut()).build();
-                    ui.color_picker(im_str!("Light Color"), light.colour.as_mut() as &mut [f32; 3]).build();
+                    ui.checkbox(im_str!("Set by hand while paused"), &mut time_of_day.override_light);
+                    if time_of_day.paused && time_of_day.override_light {
+                        ui.drag_float3(im_str!("Light position"), light.position.as_mut()).build();
+                        ui.color_picker(im_str!("Light colour"), light.colour.as_mut() as &mut [f32; 3]).build();
+                    } else {
+                        ui.text(im_str!("Following the time of day"));
+                    }
                 }
                 if ui.collapsing_header(im_str!("Fog")).build() {
                     ui.slider_float(im_str!("fog density"), &mut fog.density, 0.0, 1.0).build();
                     ui.slider_float(im_str!("fog gradient"), &mut fog.gradient, 0.0, 10.0).build();
                     ui.color_picker(im_str!("Light Color"), fog.day_colour.as_mut() as &mut [f32; 3]).build();
                 }
+                if ui.collapsing_header(im_str!("Shadows")).build() {
+                    ui.slider_float(im_str!("shadow distance"), &mut shadow_settings.distance, 10.0, 500.0).build();
+                    ui.slider_float(im_str!("shadow bias"), &mut shadow_settings.bias, 0.0, 0.02).build();
+                }
+                if ui.collapsing_header(im_str!("Post processing")).build() {
+                    let post_processing = &mut render_settings.post_processing;
+                    ui.checkbox(im_str!("Enabled"), &mut post_processing.enabled);
+                    ui.slider_int(im_str!("MSAA samples"), &mut post_processing.msaa_samples, 1, 8).build();
+                    ui.checkbox(im_str!("Bloom"), &mut post_processing.bloom);
+                    ui.slider_float(im_str!("bloom threshold"), &mut post_processing.bloom_threshold, 0.0, 3.0).build();
+                    ui.slider_float(im_str!("bloom intensity"), &mut post_processing.bloom_intensity, 0.0, 3.0).build();
+                    ui.checkbox(im_str!("Tone mapping"), &mut post_processing.tone_mapping);
+                    ui.slider_float(im_str!("exposure"), &mut post_processing.exposure, 0.1, 5.0).build();
+                    ui.checkbox(im_str!("Gamma correction"), &mut post_processing.gamma_correction);
+                    ui.slider_float(im_str!("gamma"), &mut post_processing.gamma, 1.0, 3.0).build();
+                    ui.checkbox(im_str!("FXAA"), &mut post_processing.fxaa);
+                }
+                if ui.collapsing_header(im_str!("Debug draw (F7)")).build() {
+                    let debug_draw_settings = &mut render_settings.debug_draw;
+                    ui.checkbox(im_str!("Enabled"), &mut debug_draw_settings.enabled);
+                    ui.checkbox(im_str!("Bounding boxes"), &mut debug_draw_settings.bounding_boxes);
+                    ui.checkbox(im_str!("Lights"), &mut debug_draw_settings.lights);
+                    ui.checkbox(im_str!("Player velocity"), &mut debug_draw_settings.player_velocity);
+                    ui.checkbox(im_str!("Shadow frustum"), &mut debug_draw_settings.shadow_frustum);
+                }
+                if ui.collapsing_header(im_str!("Config")).build() {
+                    ui.text(im_str!("Window size, camera, player and fog go to {}", engine_config.path().display()));
+                    if ui.small_button(im_str!("Save config")) {
+                        engine_config.update(&camera, &fog, &player_settings, &window_size);
+                        *config_save_status = Some(engine_config.save()
+                            .map(|()| format!("Saved config to {}", engine_config.path().display())));
+                    }
+                    match *config_save_status {
+                        Some(Ok(ref message)) => ui.text(im_str!("{}", message)),
+                        Some(Err(ref message)) => ui.text_colored([1.0, 0.3, 0.3, 1.0], im_str!("{}", message)),
+                        None => {}
+                    }
+                }
             });
         imgui_renderer.render(ui);
     }
 }
 
+impl DebugUiBuilder {
+    /// Where a world position appears in the debug ui's screen coordinates, none when it is behind the camera
+    fn screen_position(view_projection: &Matrix4<f32>, position: Vector3<f32>, window_size: &WindowSize) -> Option<(f32, f32)> {
+        let clip_position = *view_projection * position.extend(1.0);
+        if clip_position.w <= 0.0 {
+            return None;
+        }
+        let x = (clip_position.x / clip_position.w + 1.0) * 0.5 * window_size.window_width as f32;
+        let y = (1.0 - clip_position.y / clip_position.w) * 0.5 * window_size.window_height as f32;
+        Some((x, y))
+    }
+}
+
 
 pub struct RenderSettingsController;
 
@@ -268,6 +431,28 @@ impl<'a> System<'a> for RenderSettingsController {
             if key == &Key::Slash && (action == &Action::Press) {
                 render_settings.debug_ui = !render_settings.debug_ui
             }
+            if key == &Key::F7 && (action == &Action::Press) {
+                render_settings.debug_draw.enabled = !render_settings.debug_draw.enabled
+            }
+            if action == &Action::Press {
+                if let Some(render_mode) = RenderSettingsController::render_mode_for_key(key) {
+                    render_settings.render_mode = render_mode;
+                }
+            }
+        }
+    }
+}
+
+impl RenderSettingsController {
+    fn render_mode_for_key(key: &Key) -> Option<RenderMode> {
+        match *key {
+            Key::F1 => Some(RenderMode::Shaded),
+            Key::F2 => Some(RenderMode::Wireframe),
+            Key::F3 => Some(RenderMode::Normals),
+            Key::F4 => Some(RenderMode::UvChecker),
+            Key::F5 => Some(RenderMode::LightingOnly),
+            Key::F6 => Some(RenderMode::Overdraw),
+            _ => None
         }
     }
 }
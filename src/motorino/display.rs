@@ -20,8 +20,8 @@ impl Display {
         gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
         (Display {
             should_close: false,
-            window: window,
-            glfw: glfw
+            window,
+            glfw
         }, events)
     }
 
@@ -41,18 +41,19 @@ impl Display {
         window.make_current();
         window.set_key_polling(true);
         window.set_framebuffer_size_polling(true);
+        window.set_size_polling(true);
 
-        return (window, events, glfw);
+        (window, events, glfw)
     }
 
 
     pub fn get_time(&self) -> f64 {
-        return self.glfw.get_time();
+        self.glfw.get_time()
     }
 
     #[allow(dead_code)]
     pub fn should_close(&self) -> bool {
-        return self.should_close;
+        self.should_close
     }
 
     pub fn swap_buffers(&mut self) {
@@ -75,4 +76,14 @@ impl Display {
     pub fn get_cursor_position(&self) -> (f64, f64){
         self.window.get_cursor_pos()
     }
+
+    /// In pixels, larger than the window size on HiDPI displays
+    pub fn get_framebuffer_size(&self) -> (i32, i32) {
+        self.window.get_framebuffer_size()
+    }
+
+    /// In screen coordinates
+    pub fn get_window_size(&self) -> (i32, i32) {
+        self.window.get_size()
+    }
 }
@@ -1,4 +1,9 @@
 use super::cgmath::Vector3;
+use super::cgmath::prelude::*;
+use super::components::{PointLight, Transform};
+use super::specs::{Read, ReadStorage, System, Write};
+use super::UpdateDeltaTime;
+use std::cmp::Ordering;
 
 pub struct Light {
     pub position: Vector3<f32>,
@@ -31,3 +36,141 @@ impl Default for Fog {
         }
     }
 }
+
+/// A light as the entity and terrain shaders see it
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderLight {
+    pub position: Vector3<f32>,
+    pub colour: Vector3<f32>,
+    pub attenuation: Vector3<f32>
+}
+
+impl ShaderLight {
+    /// Fills unused slots, black so it adds nothing
+    fn none() -> ShaderLight {
+        ShaderLight {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            colour: Vector3::new(0.0, 0.0, 0.0),
+            attenuation: Vector3::new(1.0, 0.0, 0.0)
+        }
+    }
+}
+
+/// Lights uploaded to the shaders each frame, passed to them as the MAX_LIGHTS define
+pub const MAX_LIGHTS: usize = 4;
+
+/// The sun followed by the point lights nearest to `center`, padded to `MAX_LIGHTS`.
+/// The sun is always first as it is the only light casting shadows.
+pub fn nearest_lights(sun: &Light, point_lights: &ReadStorage<PointLight>, transforms: &ReadStorage<Transform>, center: Vector3<f32>) -> Vec<ShaderLight> {
+    use super::specs::Join;
+    let mut lights: Vec<ShaderLight> = (point_lights, transforms).join()
+        .map(|(point_light, transform)| ShaderLight {
+            position: transform.position + point_light.offset,
+            colour: point_light.colour,
+            attenuation: point_light.attenuation
+        })
+        .collect();
+    lights.sort_by(|a, b| {
+        let distance_a = (a.position - center).magnitude2();
+        let distance_b = (b.position - center).magnitude2();
+        distance_a.partial_cmp(&distance_b).unwrap_or(Ordering::Equal)
+    });
+    lights.truncate(MAX_LIGHTS - 1);
+    // no falloff for the sun
+    lights.insert(0, ShaderLight { position: sun.position, colour: sun.colour, attenuation: Vector3::new(1.0, 0.0, 0.0) });
+    while lights.len() < MAX_LIGHTS {
+        lights.push(ShaderLight::none());
+    }
+    lights
+}
+
+/// Clock for the day/night cycle, everything lit by the sun follows it
+pub struct TimeOfDay {
+    /// 0 to 24, noon at 12
+    pub hour: f32,
+    /// real seconds for a whole day
+    pub day_length: f32,
+    pub paused: bool,
+    /// while paused, leave the sun where the debug ui puts it
+    pub override_light: bool
+}
+
+impl Default for TimeOfDay {
+    fn default() -> TimeOfDay {
+        TimeOfDay {
+            hour: 10.0,
+            day_length: 240.0,
+            paused: false,
+            override_light: false
+        }
+    }
+}
+
+impl TimeOfDay {
+    const SUN_DISTANCE: f32 = 600.0;
+
+    /// Sine of the sun's height above the horizon, negative at night
+    pub fn sun_elevation(&self) -> f32 {
+        self.sun_angle().sin()
+    }
+
+    // rises in the east at 6 and sets in the west at 18
+    fn sun_angle(&self) -> f32 {
+        (self.hour - 6.0) / 24.0 * 2.0 * ::std::f32::consts::PI
+    }
+
+    /// How far from the day towards the night sky, fog and skybox, from 0 to 1
+    pub fn blend_factor(&self) -> f32 {
+        1.0 - smoothstep(-0.1, 0.2, self.sun_elevation())
+    }
+
+    /// The sun by day and the moon opposite it by night
+    pub fn light_position(&self) -> Vector3<f32> {
+        let angle = self.sun_angle();
+        let sun_position = Vector3::new(angle.cos() * TimeOfDay::SUN_DISTANCE, angle.sin() * TimeOfDay::SUN_DISTANCE, -20.0);
+        if self.sun_elevation() >= 0.0 { sun_position } else { -sun_position }
+    }
+
+    /// Warm near the horizon, white at midday, a faint blue moonlight at night and fading
+    /// to nothing as either crosses the horizon so the switch between them isn't seen
+    pub fn light_colour(&self) -> Vector3<f32> {
+        let elevation = self.sun_elevation();
+        if elevation >= 0.0 {
+            let horizon_colour = Vector3::new(1.0, 0.6, 0.3);
+            let noon_colour = Vector3::new(1.0, 1.0, 1.0);
+            horizon_colour.lerp(noon_colour, smoothstep(0.0, 0.4, elevation)) * smoothstep(0.0, 0.1, elevation)
+        } else {
+            Vector3::new(0.2, 0.2, 0.35) * smoothstep(0.0, 0.1, -elevation)
+        }
+    }
+
+    fn advance(&mut self, dt: f32) {
+        if self.paused || self.day_length <= 0.0 {
+            return;
+        }
+        self.hour = (self.hour + dt * 24.0 / self.day_length) % 24.0;
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Moves the sun along with the time of day
+pub struct DayNightCycle;
+
+impl<'a> System<'a> for DayNightCycle {
+    type SystemData = (Write<'a, TimeOfDay>,
+                       Write<'a, Light>,
+                       Read<'a, UpdateDeltaTime>);
+
+    fn run(&mut self, (mut time_of_day, mut light, dt): Self::SystemData) {
+        time_of_day.advance(dt.0);
+        if time_of_day.paused && time_of_day.override_light {
+            return;
+        }
+        light.position = time_of_day.light_position();
+        light.colour = time_of_day.light_colour();
+    }
+}
@@ -0,0 +1,134 @@
+use std::mem;
+use std::os::raw::c_void;
+use super::camera::Camera;
+use super::cgmath::{EuclideanSpace, Matrix4, Vector4};
+use super::components::{PointLight, Transform};
+use super::environment::{nearest_lights, Fog, Light, TimeOfDay, MAX_LIGHTS};
+use super::gl;
+use super::gl::types::*;
+use super::renderers::ScenePass;
+use super::resources::ResourceLoader;
+use super::shaders::{ShaderError, ShaderProgram};
+use super::shadows::{ShadowMap, ShadowSettings};
+use super::specs::{Read, ReadStorage, System};
+use super::{ElapsedTime, WindowSize};
+
+/// The `FrameUniforms` block in frame-uniforms.glsl, laid out as std140 has it: vec3s padded to
+/// vec4s and the size rounded up to a whole vec4
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FrameUniforms {
+    projection_matrix: Matrix4<f32>,
+    view_matrix: Matrix4<f32>,
+    light_space_matrix: Matrix4<f32>,
+    camera_position: Vector4<f32>,
+    light_position: [Vector4<f32>; MAX_LIGHTS],
+    light_colour: [Vector4<f32>; MAX_LIGHTS],
+    attenuation: [Vector4<f32>; MAX_LIGHTS],
+    day_sky_colour: Vector4<f32>,
+    night_sky_colour: Vector4<f32>,
+    blend_factor: f32,
+    fog_density: f32,
+    fog_gradient: f32,
+    shadow_bias: f32,
+    near_plane: f32,
+    far_plane: f32,
+    time: f32,
+    padding: f32
+}
+
+/// Uploads the camera, lights, fog and shadow values the scene shaders share into a uniform
+/// buffer, which every program made by `shader_program` reads. Run before each scene pass
+/// rather than once a frame, as the reflection pass mirrors the camera.
+pub struct FrameUniformUploader {
+    ubo: GLuint
+}
+
+impl FrameUniformUploader {
+    pub const BLOCK_NAME: &'static str = "FrameUniforms";
+    pub const BINDING: GLuint = 0;
+
+    pub fn new() -> FrameUniformUploader {
+        let mut ubo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut ubo);
+        }
+        FrameUniformUploader { ubo }
+    }
+
+    /// A program including frame-uniforms.glsl, compiled with the `MAX_LIGHTS` it needs and
+    /// reading the block from the uploaded buffer
+    pub fn shader_program(resource_loader: &ResourceLoader, vertex_shader: &str, fragment_shader: &str) -> Result<ShaderProgram, ShaderError> {
+        let max_lights = MAX_LIGHTS.to_string();
+        let program = ShaderProgram::from_shader_files_with_defines(resource_loader, vertex_shader, fragment_shader, &[("MAX_LIGHTS", max_lights.as_str())])?;
+        program.bind_uniform_block(FrameUniformUploader::BLOCK_NAME, FrameUniformUploader::BINDING);
+        Ok(program)
+    }
+
+    fn upload(&self, uniforms: &FrameUniforms) {
+        unsafe {
+            gl::BindBuffer(gl::UNIFORM_BUFFER, self.ubo);
+            gl::BufferData(gl::UNIFORM_BUFFER,
+                           mem::size_of::<FrameUniforms>() as GLsizeiptr,
+                           uniforms as *const FrameUniforms as *const c_void,
+                           gl::DYNAMIC_DRAW);
+            gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, FrameUniformUploader::BINDING, self.ubo);
+        }
+    }
+}
+
+impl<'a> System<'a> for FrameUniformUploader {
+    type SystemData = (ReadStorage<'a, PointLight>,
+                       ReadStorage<'a, Transform>,
+                       Read<'a, Camera>,
+                       Read<'a, Fog>,
+                       Read<'a, Light>,
+                       Read<'a, TimeOfDay>,
+                       Read<'a, ShadowMap>,
+                       Read<'a, ShadowSettings>,
+                       Read<'a, ScenePass>,
+                       Read<'a, WindowSize>,
+                       Read<'a, ElapsedTime>);
+
+    fn run(&mut self, (point_light, transform, camera, fog, light, time_of_day, shadow_map, shadow_settings, scene_pass, window_size, elapsed_time): Self::SystemData) {
+        if !scene_pass.enabled {
+            return;
+        }
+        let camera = scene_pass.camera(&camera);
+        let lights = nearest_lights(&light, &point_light, &transform, camera.position.to_vec());
+        let mut uniforms = FrameUniforms {
+            projection_matrix: camera.projection_matrix(&window_size),
+            view_matrix: camera.get_view_matrix(),
+            light_space_matrix: shadow_map.light_space_matrix,
+            camera_position: camera.position.to_vec().extend(1.0),
+            light_position: [Vector4::new(0.0, 0.0, 0.0, 0.0); MAX_LIGHTS],
+            light_colour: [Vector4::new(0.0, 0.0, 0.0, 0.0); MAX_LIGHTS],
+            attenuation: [Vector4::new(0.0, 0.0, 0.0, 0.0); MAX_LIGHTS],
+            day_sky_colour: fog.day_colour.extend(1.0),
+            night_sky_colour: fog.night_colour.extend(1.0),
+            blend_factor: time_of_day.blend_factor(),
+            fog_density: fog.density,
+            fog_gradient: fog.gradient,
+            shadow_bias: shadow_settings.bias,
+            near_plane: camera.near_plane,
+            far_plane: camera.far_plane,
+            time: elapsed_time.0,
+            padding: 0.0
+        };
+        for (i, light) in lights.iter().enumerate() {
+            uniforms.light_position[i] = light.position.extend(1.0);
+            uniforms.light_colour[i] = light.colour.extend(1.0);
+            uniforms.attenuation[i] = light.attenuation.extend(0.0);
+        }
+        self.upload(&uniforms);
+    }
+}
+
+impl Drop for FrameUniformUploader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.ubo);
+        }
+    }
+}
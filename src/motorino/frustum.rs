@@ -0,0 +1,126 @@
+use super::cgmath::{Matrix4, Vector3, Vector4};
+use super::cgmath::prelude::*;
+use super::components::BoundingBox;
+
+pub struct Frustum {
+    // left, right, bottom, top, near, far - normals point into the frustum
+    planes: [Vector4<f32>; 6]
+}
+
+impl Frustum {
+
+    /// Extracts the six clip planes from a combined projection * view matrix (Gribb/Hartmann)
+    pub fn from_matrix(projection_view: &Matrix4<f32>) -> Frustum {
+        let row0 = projection_view.row(0);
+        let row1 = projection_view.row(1);
+        let row2 = projection_view.row(2);
+        let row3 = projection_view.row(3);
+        Frustum {
+            planes: [
+                Frustum::normalize_plane(row3 + row0),
+                Frustum::normalize_plane(row3 - row0),
+                Frustum::normalize_plane(row3 + row1),
+                Frustum::normalize_plane(row3 - row1),
+                Frustum::normalize_plane(row3 + row2),
+                Frustum::normalize_plane(row3 - row2)
+            ]
+        }
+    }
+
+    fn normalize_plane(plane: Vector4<f32>) -> Vector4<f32> {
+        let length = plane.truncate().magnitude();
+        plane / length
+    }
+
+    fn distance_to_plane(plane: &Vector4<f32>, point: Vector3<f32>) -> f32 {
+        plane.truncate().dot(point) + plane.w
+    }
+
+    pub fn contains_sphere(&self, center: Vector3<f32>, radius: f32) -> bool {
+        for plane in self.planes.iter() {
+            if Frustum::distance_to_plane(plane, center) < -radius {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn contains_box(&self, bounding_box: &BoundingBox) -> bool {
+        for plane in self.planes.iter() {
+            // test the corner furthest along the plane normal, if that is outside the whole box is
+            let positive_vertex = Vector3 {
+                x: if plane.x >= 0.0 { bounding_box.max.x } else { bounding_box.min.x },
+                y: if plane.y >= 0.0 { bounding_box.max.y } else { bounding_box.min.y },
+                z: if plane.z >= 0.0 { bounding_box.max.z } else { bounding_box.min.z },
+            };
+            if Frustum::distance_to_plane(plane, positive_vertex) < 0.0 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::cgmath::{perspective, Deg, Point3};
+
+    // at the origin looking down -z, a quarter turn across and from 1 to 100 units away
+    fn frustum() -> Frustum {
+        let projection = perspective(Deg(90.0), 1.0, 1.0, 100.0);
+        let view = Matrix4::look_at(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -1.0), Vector3::unit_y());
+        Frustum::from_matrix(&(projection * view))
+    }
+
+    #[test]
+    fn planes_are_normalized() {
+        for plane in frustum().planes.iter() {
+            assert!((plane.truncate().magnitude() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn near_and_far_planes_are_at_the_clip_distances() {
+        let frustum = frustum();
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+        assert!((Frustum::distance_to_plane(&frustum.planes[4], origin) + 1.0).abs() < 1e-3);
+        assert!((Frustum::distance_to_plane(&frustum.planes[5], origin) - 100.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn spheres_in_front_are_inside() {
+        assert!(frustum().contains_sphere(Vector3::new(0.0, 0.0, -50.0), 1.0));
+    }
+
+    #[test]
+    fn spheres_behind_or_past_the_far_plane_are_outside() {
+        let frustum = frustum();
+        assert!(!frustum.contains_sphere(Vector3::new(0.0, 0.0, 50.0), 1.0));
+        assert!(!frustum.contains_sphere(Vector3::new(0.0, 0.0, -150.0), 1.0));
+        // but not once they reach back over it
+        assert!(frustum.contains_sphere(Vector3::new(0.0, 0.0, -150.0), 60.0));
+    }
+
+    #[test]
+    fn spheres_to_the_side_are_inside_only_while_they_overlap() {
+        let frustum = frustum();
+        // 10 units along the view the sides are 10 units out, so this one is 10 units past the
+        // left edge and 7.07 from the plane
+        let center = Vector3::new(-20.0, 0.0, -10.0);
+        assert!(!frustum.contains_sphere(center, 7.0));
+        assert!(frustum.contains_sphere(center, 8.0));
+    }
+
+    #[test]
+    fn boxes_are_tested_by_their_nearest_corner() {
+        let frustum = frustum();
+        let ahead = BoundingBox { min: Vector3::new(-1.0, -1.0, -51.0), max: Vector3::new(1.0, 1.0, -49.0) };
+        let behind = BoundingBox { min: Vector3::new(-1.0, -1.0, 10.0), max: Vector3::new(1.0, 1.0, 12.0) };
+        // only a corner pokes into view
+        let across_the_edge = BoundingBox { min: Vector3::new(-30.0, -1.0, -12.0), max: Vector3::new(-9.0, 1.0, -10.0) };
+        assert!(frustum.contains_box(&ahead));
+        assert!(!frustum.contains_box(&behind));
+        assert!(frustum.contains_box(&across_the_edge));
+    }
+}
@@ -5,6 +5,7 @@ use super::glfw;
 use super::gl;
 use std::sync::mpsc::Receiver;
 use super::glfw::WindowEvent;
+use super::WindowSize;
 
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -18,7 +19,9 @@ pub struct MouseState {
     pub button1: bool,
     pub button2: bool,
     pub button3: bool,
+    #[allow(dead_code)]
     pub button4: bool,
+    #[allow(dead_code)]
     pub button5: bool
 }
 pub struct InputEvent<T> {
@@ -31,6 +34,7 @@ pub type MouseEvents = Vec<InputEvent<MouseButton>>;
 
 
 pub struct WindowEventHandler {
+    #[allow(dead_code)]
     pub drag_start: Option<Vector2<f32>>,
     pub event_receiver: Receiver<(f64, WindowEvent)>
 }
@@ -45,17 +49,26 @@ impl WindowEventHandler {
 impl<'a> System<'a> for WindowEventHandler {
 
     type SystemData = (Write<'a, KeyEvents>,
-                       Write<'a, MouseEvents>);
+                       Write<'a, MouseEvents>,
+                       Write<'a, WindowSize>);
 
-    fn run(&mut self, (mut key_events, mut mouse_events): Self::SystemData) {
+    fn run(&mut self, (mut key_events, mut mouse_events, mut window_size): Self::SystemData) {
         key_events.clear();
         mouse_events.clear();
         for (_, event) in glfw::flush_messages(&self.event_receiver) {
             match event {
-                glfw::WindowEvent::FramebufferSize(width, height) => {
+                // a minimised window is zero sized, keep rendering at the last size until it comes back
+                glfw::WindowEvent::FramebufferSize(width, height) if width > 0 && height > 0 => {
                     // make sure the viewport matches the new window dimensions; note that width and
                     // height will be significantly larger than specified on retina displays.
                     unsafe { gl::Viewport(0, 0, width, height) }
+                    // projections and render targets follow from the next frame
+                    window_size.width = width as u32;
+                    window_size.height = height as u32;
+                }
+                glfw::WindowEvent::Size(width, height) if width > 0 && height > 0 => {
+                    window_size.window_width = width as u32;
+                    window_size.window_height = height as u32;
                 }
                 glfw::WindowEvent::Key(source, _, action, _) => {
                     key_events.push(InputEvent{source, action});
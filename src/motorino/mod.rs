@@ -4,20 +4,26 @@ extern crate cgmath;
 extern crate image;
 extern crate gl;
 extern crate glfw;
+extern crate gltf;
 extern crate imgui;
 extern crate imgui_opengl_renderer;
 extern crate rand;
 extern crate specs;
 extern crate tobj;
+extern crate toml;
 
 use self::camera::Camera;
 use self::camera::CameraController;
+use self::config::EngineConfig;
 use self::cgmath::Vector3;
-use self::components::{GridPosition, RawModel, PlayerFlag, SimpleTexture, Transform, Velocity};
+use self::components::{GridPosition, MultiPartModel, RawModel, PlayerFlag, PointLight, SimpleTexture, Transform, Velocity, Water};
 use self::debugui::{DebugUi, DebugUiBuilder, RenderSettingsController};
 use self::display::Display;
-use self::terrain::Terrain;
-use self::environment::{Fog, Light};
+use self::terrain::{Terrain, TerrainConfig};
+use self::noise::{NoiseConfig, NoiseHeightMap};
+use self::scenery::{Scenery, SceneryLamp, SceneryLayer};
+use self::streaming::TerrainStreamer;
+use self::environment::{DayNightCycle, Light, TimeOfDay};
 use self::glfw::Action;
 use self::input::CursorPosition;
 use self::input::KeyEvents;
@@ -26,13 +32,16 @@ use self::input::MouseState;
 use self::input::WindowEventHandler;
 use self::models::Loader;
 use self::player::PlayerController;
-use self::rand::prelude::*;
-use self::renderers::{ClearScreenRenderer, EntityRenderer, RenderSettings, TerrainRenderer};
+use self::renderers::{RenderSettings, ScenePass, SceneRenderer};
 use self::resources::ResourceLoader;
+use self::shaders::{ShaderReloader, ShaderReloadStatus};
 use self::specs::prelude::*;
 use self::specs::World;
 use self::glfw::WindowEvent;
+use std::cell::RefCell;
+use std::env;
 use std::path::Path;
+use std::process;
 use std::rc::Rc;
 use std::sync::mpsc::Receiver;
 use self::components::TerrainPhysics;
@@ -42,18 +51,27 @@ use self::debugui::DebugInfoResetter;
 use motorino::models::CubeMapDefinition;
 use motorino::components::SkyboxFlag;
 use motorino::components::Texture;
-use motorino::skybox::SkyboxRenderer;
+use self::shadows::{ShadowMap, ShadowRenderer, ShadowSettings};
 use motorino::components::SkyboxTexture;
+use self::water::WaterTextures;
+use self::postprocessing::{HdrScene, PostProcessingRenderer};
+use self::debugdraw::{DebugDraw, DebugDrawRenderer, SceneDebugDrawer};
 
 #[macro_use]
 pub mod macros;
 
 pub mod renderers;
 pub mod shaders;
+pub mod preprocessor;
 pub mod resources;
 pub mod models;
+pub mod assets;
 pub mod terrain;
+pub mod noise;
+pub mod streaming;
+pub mod scenery;
 pub mod camera;
+pub mod frustum;
 pub mod environment;
 pub mod components;
 pub mod debugui;
@@ -62,40 +80,79 @@ pub mod input;
 pub mod display;
 pub mod ringbuffer;
 pub mod skybox;
+pub mod shadows;
+pub mod water;
+pub mod postprocessing;
+pub mod debugdraw;
+pub mod config;
+pub mod frameuniforms;
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct UpdateDeltaTime(f32);
 
-#[derive(Clone, Copy, Debug)]
+/// Seconds since the first frame, the same for every pass drawn in a frame
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElapsedTime(f32);
+
+/// The frame buffer size in pixels, which everything is rendered at, and the window size in screen
+/// coordinates, which the cursor and debug ui use. They differ on HiDPI displays.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct WindowSize {
     width: u32,
-    height: u32
+    height: u32,
+    window_width: u32,
+    window_height: u32
 }
 
 impl Default for WindowSize {
     fn default() -> Self {
-        WindowSize {width: Motorino::WIDTH, height: Motorino::HEIGHT}
+        WindowSize {width: Motorino::WIDTH, height: Motorino::HEIGHT, window_width: Motorino::WIDTH, window_height: Motorino::HEIGHT}
+    }
+}
+
+impl WindowSize {
+    /// Frame buffer pixels per screen coordinate
+    pub fn hidpi_factor(&self) -> f64 {
+        self.width as f64 / self.window_width as f64
     }
 }
 
 pub struct Motorino {
-    resource_loader: Rc<ResourceLoader>
+    resource_loader: Rc<ResourceLoader>,
+    config: EngineConfig,
+    terrain_config: TerrainConfig
 }
 
 impl Motorino {
 
     pub const WIDTH: u32 = 800;
     pub const HEIGHT: u32 = 800;
+    // terrain chunks loaded in each direction around the player
+    const TERRAIN_VIEW_DISTANCE: i32 = 1;
 
     pub fn new() -> Motorino {
-        Motorino {resource_loader: Rc::new(ResourceLoader::from_relative_path(Path::new("res-output")).unwrap()) }
+        let config = match EngineConfig::from_args(env::args().skip(1)) {
+            Ok(config) => config,
+            Err(message) => {
+                eprintln!("{}\n{}", message, EngineConfig::USAGE);
+                process::exit(2);
+            }
+        };
+        let resource_loader = match ResourceLoader::from_relative_path(Path::new(&config.resource_directory)) {
+            Ok(resource_loader) => Rc::new(resource_loader),
+            Err(message) => {
+                eprintln!("{}\n{}", message, EngineConfig::USAGE);
+                process::exit(2);
+            }
+        };
+        Motorino { resource_loader, config, terrain_config: TerrainConfig::default() }
     }
 
     fn create_display(&self) -> (Display, Receiver<(f64, WindowEvent)>) {
-        Display::create(Motorino::WIDTH, Motorino::HEIGHT)
+        Display::create(self.config.window.width, self.config.window.height)
     }
 
-    fn create_world(&self, mut loader: &mut Loader, debug_ui: DebugUi) -> World {
+    fn create_world(&self, loader: &RefCell<Loader>, terrain_streamer: &mut TerrainStreamer, debug_ui: DebugUi) -> Result<World, String> {
         let mut world = World::new();
         world.register::<Velocity>();
         world.register::<Transform>();
@@ -103,37 +160,54 @@ impl Motorino {
         world.register::<TerrainPhysics>();
         world.register::<GridPosition>();
         world.register::<RawModel>();
+        world.register::<MultiPartModel>();
         world.register::<SimpleTexture>();
         world.register::<Texture>();
         world.register::<PlayerFlag>();
         world.register::<SkyboxFlag>();
         world.register::<SkyboxTexture>();
+        world.register::<PointLight>();
+        world.register::<Water>();
 
         world.add_resource(UpdateDeltaTime::default());
+        world.add_resource(ElapsedTime::default());
         world.add_resource(DebugInfo::default());
         world.add_resource(Light::default());
-        world.add_resource(Fog::default());
-        world.add_resource(Camera::default());
+        world.add_resource(self.config.fog.to_fog());
+        world.add_resource(TimeOfDay::default());
+        let mut camera = Camera::default();
+        self.config.camera.apply(&mut camera);
+        world.add_resource(camera);
+        world.add_resource(self.config.player);
+        world.add_resource(self.config.clone());
+        world.add_resource(self.terrain_config);
         world.add_resource(WindowSize::default());
         world.add_resource(CursorPosition::default());
         world.add_resource(KeyEvents::default());
         world.add_resource(MouseEvents::default());
         world.add_resource(MouseState::default());
         world.add_resource(RenderSettings::default());
+        world.add_resource(ShadowSettings::default());
+        world.add_resource(ShadowMap::default());
+        world.add_resource(ScenePass::default());
+        world.add_resource(WaterTextures::default());
+        world.add_resource(HdrScene::default());
+        world.add_resource(DebugDraw::default());
+        world.add_resource(ShaderReloadStatus::default());
         world.add_resource(debug_ui);
 
-        let mut rng = thread_rng();
-        Motorino::create_terrain(&mut world, &mut loader, &self.resource_loader);
+        Motorino::create_player(&mut world, &mut loader.borrow_mut())?;
+        // stream in the ground and its scenery around the player before anything else is placed
+        // on it, the streamer borrows the loader too
+        terrain_streamer.run_now(&world.res);
+        world.maintain();
 
-        Motorino::create_multiple_entities(&mut world, &mut loader, &mut rng, "models/tree1b.obj", "textures/tree1.jpg", 50);
-        Motorino::create_multiple_entities(&mut world, &mut loader, &mut rng, "models/tree2b.obj", "textures/tree2.jpg", 100);
-        Motorino::create_multiple_entities(&mut world, &mut loader, &mut rng, "models/tree3b.obj", "textures/tree3.jpg", 300);
-//        Motorino::create_multiple_entities(&mut world, &mut loader, &mut rng, "models/tree3b.obj", "textures/tree3.jpg", 1);
+        let mut loader = loader.borrow_mut();
 
+        Motorino::create_multi_part_entity(&mut world, &mut loader, "models/Low-Poly-Racing-Car.obj", Vector3 { x: 30.0, y: 0.0, z: 30.0 }, 3.0);
         Motorino::create_skybox(&mut world, &mut loader);
-        Motorino::create_player(&mut world, &mut loader);
 
-        world
+        Ok(world)
     }
 
     fn create_skybox(world: &mut World, loader: &mut Loader) {
@@ -208,83 +282,143 @@ const cubmap_vertex_positions: [f32; 108] = [
             .build();
     }
 
-    fn create_player(world: &mut World, loader: &mut Loader) {
+    fn create_player(world: &mut World, loader: &mut Loader) -> Result<(), String> {
         world.create_entity()
             .with(Transform { position: Vector3 { x: 0.0, y: 0.0, z: 0.0 }, ..Transform::default() })
-            .with(loader.load_from_obj("models/lego-man.obj"))
+            .with(loader.load_from_obj("models/lego-man.obj")?)
             .with(loader.load_simple_texture("textures/lego-man.jpg", 0.0, 20.0).unwrap())
             .with(Velocity::default())
             .with(PlayerFlag {})
+            // a lantern held up above the head
+            .with(PointLight { colour: Vector3 { x: 1.0, y: 0.8, z: 0.5 }, offset: Vector3 { x: 0.0, y: 8.0, z: 0.0 }, ..PointLight::default() })
             .build();
+        Ok(())
     }
 
-    fn create_terrain(world: &mut World, mut loader: &mut Loader, resource_loader: &ResourceLoader) {
-        let height_map = resource_loader.load_image("textures/heightmap.png").unwrap();
-        let terrain_texture_pack = TerrainTexturePack::new(loader, "textures/grass.jpg", "textures/mud.jpg", "textures/grass-flowers.jpg", "textures/path.jpg", "textures/blend-map.jpg");
-        let terrain = Terrain::new(&mut loader, 0, 0, &height_map);
-        let terrain2 = Terrain::new(&mut loader, 1, 0, &height_map);
-        world.create_entity()
-            .with(GridPosition { x: 0, z: 0 })
-            .with(terrain_texture_pack)
-            .with(terrain.raw_model)
-            .with(TerrainPhysics { heights:terrain.heights, x: terrain.x, z: terrain.z})
-            .build();
-        world.create_entity()
-            .with(GridPosition { x: 1, z: 0 })
-            .with(terrain_texture_pack)
-            .with(terrain2.raw_model)
-            .with(TerrainPhysics { heights:terrain2.heights, x: terrain2.x, z: terrain2.z})
-            .build();
+    /// What the terrain streamer places on every chunk
+    fn create_scenery(loader: &mut Loader) -> Result<Scenery, String> {
+        let layers = vec![
+            Motorino::create_tree_layer(loader, "models/tree1b.obj", "textures/tree1.jpg", 50)?,
+            Motorino::create_tree_layer(loader, "models/tree2b.obj", "textures/tree2.jpg", 100)?,
+            Motorino::create_tree_layer(loader, "models/tree3b.obj", "textures/tree3.jpg", 300)?,
+            Motorino::create_foliage_layer(loader, "textures/grassy.png", 300)?,
+            Motorino::create_foliage_layer(loader, "textures/flower.png", 100)?
+        ];
+        let lamps = vec![
+            SceneryLamp { offset: Vector3 { x: 60.0, y: 0.0, z: 40.0 }, colour: Vector3 { x: 2.0, y: 0.0, z: 0.0 } },
+            SceneryLamp { offset: Vector3 { x: 120.0, y: 0.0, z: 90.0 }, colour: Vector3 { x: 0.0, y: 2.0, z: 2.0 } },
+            SceneryLamp { offset: Vector3 { x: 200.0, y: 0.0, z: 60.0 }, colour: Vector3 { x: 2.0, y: 2.0, z: 0.0 } }
+        ];
+        Ok(Scenery { layers, lamps, lakes: true })
     }
 
-    fn create_multiple_entities(world: &mut World, loader: &mut Loader, rng: &mut ThreadRng, model: &str, texture: &str, count: i32) {
-        let model = loader.load_from_obj(&model);
-        let tex = loader.load_simple_texture(&texture, 0.0, 20.0).unwrap();
+    fn create_tree_layer(loader: &mut Loader, model: &str, texture: &str, count_per_chunk: usize) -> Result<SceneryLayer, String> {
+        let model = loader.load_from_obj(model)?;
+        let texture = loader.load_simple_texture(texture, 0.0, 20.0)?;
+        Ok(SceneryLayer { model, texture, count_per_chunk, scale: 2.5 })
+    }
 
-        for _i in 0..count {
-            let x: f32 = rng.gen::<f32>() * Terrain::SIZE as f32;
-            let z: f32 = rng.gen::<f32>() * Terrain::SIZE as f32;
+    fn create_foliage_layer(loader: &mut Loader, texture: &str, count_per_chunk: usize) -> Result<SceneryLayer, String> {
+        // two quads crossing at right angles so the cut-out texture reads from every side
+        const crossed_quad_positions: [f32; 24] = [
+            -0.5, 1.0, 0.0,   0.5, 1.0, 0.0,   0.5, 0.0, 0.0,   -0.5, 0.0, 0.0,
+            0.0, 1.0, -0.5,   0.0, 1.0, 0.5,   0.0, 0.0, 0.5,   0.0, 0.0, -0.5];
+        const crossed_quad_texture_coords: [f32; 16] = [
+            0.0, 0.0,   1.0, 0.0,   1.0, 1.0,   0.0, 1.0,
+            0.0, 0.0,   1.0, 0.0,   1.0, 1.0,   0.0, 1.0];
+        const crossed_quad_normals: [f32; 24] = [
+            0.0, 0.0, 1.0,   0.0, 0.0, 1.0,   0.0, 0.0, 1.0,   0.0, 0.0, 1.0,
+            1.0, 0.0, 0.0,   1.0, 0.0, 0.0,   1.0, 0.0, 0.0,   1.0, 0.0, 0.0];
+        const crossed_quad_indices: [u32; 12] = [
+            0, 3, 2,   2, 1, 0,
+            4, 7, 6,   6, 5, 4];
+
+        let model = loader.load_to_vao(&crossed_quad_positions, &crossed_quad_texture_coords, &crossed_quad_normals, &crossed_quad_indices);
+        let texture = SimpleTexture {
+            has_transparency: true,
+            use_fake_lighting: true,
+            ..loader.load_simple_texture(texture, 0.0, 1.0)?
+        };
+        Ok(SceneryLayer { model, texture, count_per_chunk, scale: 4.0 })
+    }
 
-            let height = {
-                let terrain_physics: ReadStorage<TerrainPhysics> = world.read_storage();
-                let grid_position: ReadStorage<GridPosition> = world.read_storage();
+    fn create_multi_part_entity(world: &mut World, loader: &mut Loader, model: &str, position: Vector3<f32>, scale: f32) {
+        let model = loader.load_multi_part_model(model).unwrap();
+        let height = {
+            let terrain_physics: ReadStorage<TerrainPhysics> = world.read_storage();
+            let grid_position: ReadStorage<GridPosition> = world.read_storage();
 
-                Terrain::get_height_for_position(&grid_position, &terrain_physics, x, z)
-            };
+            Terrain::get_height_for_position(&grid_position, &terrain_physics, position.x, position.z)
+        };
 
-            world.create_entity()
-                .with(Transform{position: Vector3{ x: x, y: height, z: z}, scale: Vector3{x:2.5, y:2.5, z:2.5},..Transform::default()})
-                .with(model.clone())
-                .with( tex.clone())
-                .build();
-        }
+        world.create_entity()
+            .with(Transform{position: Vector3{ y: height, ..position}, scale: Vector3{x: scale, y: scale, z: scale}, ..Transform::default()})
+            .with(model)
+            .build();
     }
 
+    fn create_terrain_streamer(&self, loader: Rc<RefCell<Loader>>) -> Result<TerrainStreamer, String> {
+        let height_source = NoiseHeightMap::new(NoiseConfig { seed: 1234, ..NoiseConfig::default() });
+        let scenery = Motorino::create_scenery(&mut loader.borrow_mut())?;
+        TerrainStreamer::new(loader, self.terrain_config, height_source, scenery, Motorino::TERRAIN_VIEW_DISTANCE)
+    }
 
-    fn create_dispatcher(&self, event_receiver: Receiver<(f64, WindowEvent)>) -> Dispatcher {
+    fn create_dispatcher(&self, event_receiver: Receiver<(f64, WindowEvent)>, terrain_streamer: TerrainStreamer, window_size: &WindowSize) -> Result<Dispatcher<'_, '_>, String> {
         let dispatcher = DispatcherBuilder::new()
             .with(CameraController, "camera-controller", &[])
             .with(PlayerController::default(), "player-controller", &[])
             .with(RenderSettingsController, "render-settings-controller", &[])
+            .with(DayNightCycle, "day-night-cycle", &[])
+            .with(SceneDebugDrawer, "scene-debug-drawer", &["player-controller"])
             .with_thread_local(DebugInfoResetter)
-            .with_thread_local(ClearScreenRenderer)
-            .with_thread_local(TerrainRenderer::new(&self.resource_loader))
-            .with_thread_local(EntityRenderer::new(&self.resource_loader))
-            .with_thread_local(SkyboxRenderer::new(&self.resource_loader))
-            .with_thread_local(DebugUiBuilder)
+            .with_thread_local(ShaderReloader::new(self.resource_loader.clone()))
+            .with_thread_local(terrain_streamer)
+            .with_thread_local(ShadowRenderer::new(&self.resource_loader)?)
+            .with_thread_local(SceneRenderer::new(&self.resource_loader, window_size)?)
+            .with_thread_local(PostProcessingRenderer::new(&self.resource_loader, window_size)?)
+            .with_thread_local(DebugDrawRenderer::new(&self.resource_loader)?)
+            .with_thread_local(DebugUiBuilder::default())
             .with_thread_local(WindowEventHandler::new(event_receiver))
             .build();
-        dispatcher
+        Ok(dispatcher)
     }
 
     pub fn run(&mut self) {
         let (mut display, event_receiver) = self.create_display();
-        let mut loader = Loader::new(self.resource_loader.clone());
+        // the terrain streamer loads chunks with the same loader, so they share its cache
+        let loader = Rc::new(RefCell::new(Loader::new(self.resource_loader.clone())));
 
         let debug_ui = DebugUi::new(|s| display.window.get_proc_address(s) as _);
-        let mut world = self.create_world(&mut loader, debug_ui);
+        let mut terrain_streamer = match self.create_terrain_streamer(loader.clone()) {
+            Ok(terrain_streamer) => terrain_streamer,
+            Err(message) => {
+                eprintln!("{}", message);
+                return;
+            }
+        };
+        let mut world = match self.create_world(&loader, &mut terrain_streamer, debug_ui) {
+            Ok(world) => world,
+            Err(message) => {
+                eprintln!("{}", message);
+                return;
+            }
+        };
 
-        let mut update_dispatcher = self.create_dispatcher(event_receiver);
+        // the frame buffer can be larger than asked for on HiDPI displays
+        let window_size = {
+            let (width, height) = display.get_framebuffer_size();
+            let (window_width, window_height) = display.get_window_size();
+            WindowSize { width: width as u32, height: height as u32, window_width: window_width as u32, window_height: window_height as u32 }
+        };
+        *world.write_resource::<WindowSize>() = window_size;
+
+        let mut update_dispatcher = match self.create_dispatcher(event_receiver, terrain_streamer, &window_size) {
+            Ok(update_dispatcher) => update_dispatcher,
+            Err(message) => {
+                eprintln!("{}", message);
+                return;
+            }
+        };
 
         #[allow(unused_assignments)]
         let mut delta_time: f32 = 0.0;
@@ -295,33 +429,47 @@ const cubmap_vertex_positions: [f32; 108] = [
             delta_time = current_frame - last_frame;
             last_frame = current_frame;
 
-//            let () = {
+//            {
 //                let mut frame_times = world.write_resource::<RingBuffer<f32>>();
 //                frame_times.push(delta_time);
-//            };
+//            }
 
-            let () = {
+            {
                 let mut delta = world.write_resource::<UpdateDeltaTime>();
                 *delta = UpdateDeltaTime(delta_time);
-            };
+            }
+
+            {
+                let mut elapsed = world.write_resource::<ElapsedTime>();
+                *elapsed = ElapsedTime(elapsed.0 + delta_time);
+            }
 
 
-            let () = {
+            {
                 let mut mouse_state = world.write_resource::<MouseState>();
                 mouse_state.button1 = display.mouse_action(glfw::MouseButtonLeft) == Action::Press;
                 mouse_state.button2 = display.mouse_action(glfw::MouseButtonMiddle) == Action::Press;
                 mouse_state.button3 = display.mouse_action(glfw::MouseButtonRight) == Action::Press;
-            };
+            }
 
-            let () = {
+            {
                 let mut cursor_position = world.write_resource::<CursorPosition>();
                 let (x, y) = display.get_cursor_position();
                 cursor_position.x = x as f32;
                 cursor_position.y = y as f32;
-            };
+            }
 
-            update_dispatcher.dispatch(&mut world.res);
+            update_dispatcher.dispatch(&world.res);
             world.maintain();
+            world.write_resource::<DebugDraw>().clear();
+
+            // components removed this frame may have dropped the last handle to an asset
+            {
+                let mut loader = loader.borrow_mut();
+                loader.release_unused();
+                let mut debug_info = world.write_resource::<DebugInfo>();
+                debug_info.asset_stats = loader.stats();
+            }
 
             display.swap_buffers();
             display.poll_events();
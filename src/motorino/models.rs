@@ -2,17 +2,23 @@ use super::resources::ResourceLoader;
 use super::gl::types::*;
 use super::gl;
 use super::tobj;
+use super::gltf;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
 use std::path::Path;
 use std::rc::Rc;
-use super::image::GenericImageView;
 use super::components::Texture;
 use super::components::SimpleTexture;
 use super::components::RawModel;
-use super::image::DynamicImage;
-use std::collections::HashMap;
+use super::components::BoundingBox;
+use super::components::{ModelPart, MultiPartModel};
+use super::cgmath::{Matrix4, Vector3, Vector4};
+use super::cgmath::prelude::*;
+use super::image::{ColorType, DynamicImage};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, Weak};
+use super::assets::{AssetCache, AssetHandle, AssetStats, GpuAsset, GpuResource, ReleaseQueue};
 
 pub struct CubeMapDefinition {
     pub back: String,
@@ -25,108 +31,477 @@ pub struct CubeMapDefinition {
 
 pub struct Loader {
     resource_loader: Rc<ResourceLoader>,
-    pub vaos: Vec<GLuint>,
-    pub vbos: Vec<GLuint>,
-    pub textures: Vec<GLuint>,
-    pub textures_paths: HashMap<GLuint, String>
+    release_queue: ReleaseQueue,
+    live_resources: HashSet<GpuResource>,
+    released_count: usize,
+    texture_cache: AssetCache<GLuint>,
+    model_cache: AssetCache<RawModel>,
+    multi_part_model_cache: AssetCache<Vec<CachedPart>>
+}
+
+// a glTF primitive read out of its buffers with the node transforms baked in, before anything is uploaded
+struct GltfPrimitive {
+    positions: Vec<f32>,
+    texture_coords: Vec<f32>,
+    normals: Vec<f32>,
+    indices: Vec<u32>,
+    // the image sampled for the base colour, the default texture is used without one
+    image: Option<usize>,
+    texture: SimpleTexture
+}
+
+// a part as cached, its texture handle held weakly like everything else in the cache
+#[derive(Clone)]
+struct CachedPart {
+    part: ModelPart,
+    texture_asset: Option<Weak<GpuAsset>>
 }
 
 impl Loader {
 
+    const DEFAULT_TEXTURE: &'static str = "textures/white.png";
+
     pub fn new(resource_loader: Rc<ResourceLoader>) -> Loader {
         Loader {
             resource_loader,
-            vaos: Vec::new(),
-            vbos: Vec::new(),
-            textures: Vec::new(),
-            textures_paths: HashMap::new()
+            release_queue: Arc::new(Mutex::new(Vec::new())),
+            live_resources: HashSet::new(),
+            released_count: 0,
+            texture_cache: AssetCache::new(),
+            model_cache: AssetCache::new(),
+            multi_part_model_cache: AssetCache::new()
+        }
+    }
+
+    fn create_asset(&mut self, resources: Vec<GpuResource>) -> AssetHandle {
+        self.live_resources.extend(resources.iter().cloned());
+        GpuAsset::new(resources, &self.release_queue)
+    }
+
+    /// Deletes the gl objects of every asset no longer referenced by a component
+    pub fn release_unused(&mut self) {
+        let released: Vec<GpuResource> = match self.release_queue.lock() {
+            Ok(mut release_queue) => release_queue.drain(..).collect(),
+            Err(_) => return
+        };
+        if released.is_empty() {
+            return;
+        }
+        for resource in released {
+            self.delete_resource(resource);
         }
+        self.texture_cache.prune();
+        self.model_cache.prune();
+        self.multi_part_model_cache.prune();
+    }
+
+    fn delete_resource(&mut self, resource: GpuResource) {
+        if !self.live_resources.remove(&resource) {
+            return;
+        }
+        unsafe {
+            match resource {
+                GpuResource::VertexArray(vao_id) => gl::DeleteVertexArrays(1, &vao_id),
+                GpuResource::Buffer(vbo_id) => gl::DeleteBuffers(1, &vbo_id),
+                GpuResource::Texture(texture_id) => gl::DeleteTextures(1, &texture_id)
+            }
+        }
+        self.released_count += 1;
+    }
+
+    pub fn stats(&self) -> AssetStats {
+        let mut stats = AssetStats {
+            cached_textures: self.texture_cache.len(),
+            cached_models: self.model_cache.len() + self.multi_part_model_cache.len(),
+            released: self.released_count,
+            ..AssetStats::default()
+        };
+        for resource in self.live_resources.iter() {
+            match *resource {
+                GpuResource::VertexArray(_) => stats.vertex_arrays += 1,
+                GpuResource::Buffer(_) => stats.buffers += 1,
+                GpuResource::Texture(_) => stats.textures += 1
+            }
+        }
+        stats
     }
 
     pub fn load_to_vao(&mut self, positions: &[f32], texture_coords: &[f32], normals: &[f32], indices: &[u32]) -> RawModel {
+        let mut resources = Vec::new();
+        let raw_model = self.store_mesh(positions, texture_coords, normals, indices, &mut resources);
+        let asset = self.create_asset(resources);
+        RawModel { asset: Some(asset), ..raw_model }
+    }
+
+    // without indices the vertex array gets no index buffer, whoever draws it binds their own, and the
+    // count is of vertices instead
+    fn store_mesh(&mut self, positions: &[f32], texture_coords: &[f32], normals: &[f32], indices: &[u32], resources: &mut Vec<GpuResource>) -> RawModel {
         let vao_id = Loader::create_vao();
-        self.vaos.push(vao_id);
-        self.bind_indices_buffer(indices);
-        self.store_data_in_attribute_list(0, 3, &positions);
-        self.store_data_in_attribute_list(1, 2, &texture_coords);
-        self.store_data_in_attribute_list(2, 3, &normals);
+        resources.push(GpuResource::VertexArray(vao_id));
+        if !indices.is_empty() {
+            resources.push(GpuResource::Buffer(Loader::bind_indices_buffer(indices)));
+        }
+        resources.push(GpuResource::Buffer(Loader::store_data_in_attribute_list(0, 3, positions)));
+        resources.push(GpuResource::Buffer(Loader::store_data_in_attribute_list(1, 2, texture_coords)));
+        resources.push(GpuResource::Buffer(Loader::store_data_in_attribute_list(2, 3, normals)));
         Loader::unbind_vao();
         RawModel {
-            vao_id: vao_id,
-            vertex_count: indices.len()
+            vao_id,
+            vertex_count: if indices.is_empty() { positions.len() / 3 } else { indices.len() },
+            bounding_box: BoundingBox::from_positions(positions),
+            asset: None
         }
     }
 
     pub fn load_positions_to_vao(&mut self, positions: &[f32], dimension: i32) -> RawModel {
         let vao_id = Loader::create_vao();
-        self.vaos.push(vao_id);
-        self.store_data_in_attribute_list(0, dimension, &positions);
+        let vbo_id = Loader::store_data_in_attribute_list(0, dimension, positions);
         Loader::unbind_vao();
+        let asset = self.create_asset(vec![GpuResource::VertexArray(vao_id), GpuResource::Buffer(vbo_id)]);
         RawModel {
-            vao_id: vao_id,
-            vertex_count: positions.len()/dimension as usize
+            vao_id,
+            vertex_count: positions.len()/dimension as usize,
+            bounding_box: BoundingBox::default(),
+            asset: Some(asset)
+        }
+    }
+
+
+    pub fn load_from_obj(&mut self, path: &str) -> Result<RawModel, String> {
+        if let Some((raw_model, asset)) = self.model_cache.get(path) {
+            return Ok(RawModel { asset: Some(asset), ..raw_model });
+        }
+        let obj_path = self.resource_loader.to_real_path(Path::new(path));
+        let (models, _materials) = tobj::load_obj(obj_path.as_path())
+            .map_err(|e| format!("Unable to load obj {}: {:?}", path, e))?;
+        let mut resources = Vec::new();
+        let raw_model = match models.first() {
+            Some(m) => self.store_obj_mesh(&m.mesh, &mut resources),
+            None => return Err(format!("No objects in obj {}", path))
+        };
+        let asset = self.create_asset(resources);
+        self.model_cache.insert(path, raw_model.clone(), &asset);
+        Ok(RawModel { asset: Some(asset), ..raw_model })
+    }
+
+    /// glTF for `.gltf` and `.glb` files, OBJ for anything else
+    pub fn load_multi_part_model(&mut self, path: &str) -> Result<MultiPartModel, String> {
+        if path.ends_with(".gltf") || path.ends_with(".glb") {
+            self.load_from_gltf(path)
+        } else {
+            self.load_multi_part_obj(path)
         }
     }
 
+    pub fn load_multi_part_obj(&mut self, path: &str) -> Result<MultiPartModel, String> {
+        if let Some(model) = self.cached_multi_part_model(path) {
+            return Ok(model);
+        }
+        let obj_path = self.resource_loader.to_real_path(Path::new(path));
+        let (models, materials) = tobj::load_obj(obj_path.as_path())
+            .map_err(|e| format!("Unable to load obj {}: {:?}", path, e))?;
+
+        let mut material_textures = Vec::with_capacity(materials.len());
+        for material in materials.iter() {
+            material_textures.push(self.load_material(path, material)?);
+        }
+
+        let mut resources = Vec::new();
+        let mut parts = Vec::with_capacity(models.len());
+        for m in models.iter() {
+            let raw_model = self.store_obj_mesh(&m.mesh, &mut resources);
+            let texture = match m.mesh.material_id {
+                Some(material_id) if material_id < material_textures.len() => material_textures[material_id].clone(),
+                _ => self.load_simple_texture(Loader::DEFAULT_TEXTURE, 0.0, 1.0)?
+            };
+            parts.push(ModelPart { raw_model, texture });
+        }
+        Ok(self.cache_multi_part_model(path, parts, resources))
+    }
 
-    pub fn load_from_obj(&mut self, path: &str) -> RawModel {
-        let obj_path = self.resource_loader.to_real_path(&Path::new(path));
-        let (models, _materials) = tobj::load_obj(obj_path.as_path()).unwrap();
-        for (_i, m) in models.iter().enumerate() {
-            let indices = &m.mesh.indices;
-            let positions = &m.mesh.positions;
-            let texture_coords = &m.mesh.texcoords;
-            let normals = &m.mesh.normals;
-            return self.load_to_vao(positions, texture_coords, normals, indices);
+    // a miss if any of the textures has been released since
+    fn cached_multi_part_model(&self, path: &str) -> Option<MultiPartModel> {
+        let (cached_parts, asset) = self.multi_part_model_cache.get(path)?;
+        let mut parts = Vec::with_capacity(cached_parts.len());
+        for cached_part in cached_parts {
+            let texture_asset = match cached_part.texture_asset {
+                Some(texture_asset) => Some(texture_asset.upgrade()?),
+                None => None
+            };
+            let texture = SimpleTexture { asset: texture_asset, ..cached_part.part.texture };
+            parts.push(ModelPart { texture, ..cached_part.part });
         }
-        tobj::load_mtl(obj_path.as_path()).unwrap();
-        return RawModel{vao_id:0, vertex_count: 0};
+        Some(Loader::attach_to_parts(parts, &asset))
+    }
+
+    // all parts share one asset for their meshes, textures keep their own handles
+    fn cache_multi_part_model(&mut self, path: &str, parts: Vec<ModelPart>, resources: Vec<GpuResource>) -> MultiPartModel {
+        let asset = self.create_asset(resources);
+        let cached_parts = parts.iter()
+            .map(|part| CachedPart {
+                part: ModelPart { texture: SimpleTexture { asset: None, ..part.texture.clone() }, ..part.clone() },
+                texture_asset: part.texture.asset.as_ref().map(Arc::downgrade)
+            })
+            .collect();
+        self.multi_part_model_cache.insert(path, cached_parts, &asset);
+        Loader::attach_to_parts(parts, &asset)
+    }
+
+    fn attach_to_parts(parts: Vec<ModelPart>, asset: &AssetHandle) -> MultiPartModel {
+        let parts = parts.into_iter()
+            .map(|part| ModelPart {
+                raw_model: RawModel { asset: Some(asset.clone()), ..part.raw_model },
+                texture: part.texture
+            })
+            .collect();
+        MultiPartModel { parts }
+    }
+
+    fn store_obj_mesh(&mut self, mesh: &tobj::Mesh, resources: &mut Vec<GpuResource>) -> RawModel {
+        // not every object has texture coordinates or normals but the entity shader expects both
+        let vertex_count = mesh.positions.len() / 3;
+        let texture_coords = if mesh.texcoords.is_empty() { vec![0.0; vertex_count * 2] } else { mesh.texcoords.clone() };
+        let normals = if mesh.normals.is_empty() { vec![0.0; vertex_count * 3] } else { mesh.normals.clone() };
+        self.store_mesh(&mesh.positions, &texture_coords, &normals, &mesh.indices, resources)
+    }
+
+    fn load_material(&mut self, obj_path: &str, material: &tobj::Material) -> Result<SimpleTexture, String> {
+        let reflectivity = (material.specular[0] + material.specular[1] + material.specular[2]) / 3.0;
+        let shine_damper = material.shininess.max(1.0);
+        let (texture_path, diffuse_colour) = match self.find_material_texture(obj_path, &material.diffuse_texture) {
+            Some(texture_path) => (texture_path, Vector3::new(1.0, 1.0, 1.0)),
+            None => (Loader::DEFAULT_TEXTURE.to_owned(), Vector3::new(material.diffuse[0], material.diffuse[1], material.diffuse[2]))
+        };
+        let mut texture = self.load_simple_texture(&texture_path, reflectivity, shine_damper)?;
+        texture.diffuse_colour = diffuse_colour;
+        Ok(texture)
+    }
+
+    // exported mtl files often point at a path on the artist's machine, so we also look in textures/
+    fn find_material_texture(&self, obj_path: &str, texture: &str) -> Option<String> {
+        if texture.is_empty() {
+            return None;
+        }
+        let texture = texture.replace("\\", "/");
+        let file_name = Path::new(&texture).file_name()?.to_owned();
+        let next_to_obj = Path::new(obj_path).parent().unwrap_or(Path::new("")).join(&texture);
+        let in_textures = Path::new("textures").join(file_name);
+        for candidate in [next_to_obj, in_textures].iter() {
+            if self.resource_loader.to_real_path(candidate).is_file() {
+                return candidate.to_str().map(|candidate| candidate.to_owned());
+            }
+        }
+        None
     }
 
     pub fn load_simple_texture(&mut self, path: &str, reflectivity: f32, shine_damper: f32) -> Result<SimpleTexture, String> {
-        let texture = self.load_texture(path);
-        return Ok(SimpleTexture{texture_id: texture, reflectivity: reflectivity, shine_damper: shine_damper});
+        let (texture, asset) = self.load_texture(path);
+        Ok(SimpleTexture{texture_id: texture, reflectivity, shine_damper, asset: Some(asset), ..SimpleTexture::default()})
     }
 
     pub fn load_terrain_texture(&mut self, path: &str) -> Texture {
-        Texture { texture_id: self.load_texture(path)}
+        let (texture, asset) = self.load_texture(path);
+        Texture { texture_id: texture, asset: Some(asset) }
+    }
+
+    fn load_texture(&mut self, path: &str) -> (GLuint, AssetHandle) {
+        if let Some(cached) = self.texture_cache.get(path) {
+            return cached;
+        }
+        // TODO handle errors
+        let image = self.resource_loader.load_image(path).unwrap();
+        let texture = if Loader::has_alpha(&image) {
+            let image = image.to_rgba();
+            Loader::upload_texture(image.width(), image.height(), gl::RGBA, gl::RGBA, &image.into_raw())
+        } else {
+            let image = image.to_rgb();
+            Loader::upload_texture(image.width(), image.height(), gl::RGB, gl::RGB, &image.into_raw())
+        };
+        let asset = self.create_asset(vec![GpuResource::Texture(texture)]);
+        self.texture_cache.insert(path, texture, &asset);
+        (texture, asset)
+    }
+
+    fn has_alpha(image: &DynamicImage) -> bool {
+        // the bit depth is the parameter, so 16 bit images match as well
+        matches!(image.color(), ColorType::RGBA(_) | ColorType::BGRA(_) | ColorType::GrayA(_))
     }
 
-    fn load_texture(&mut self, path: &str) -> GLuint {
+    pub fn upload_texture(width: u32, height: u32, internal_format: GLenum, format: GLenum, data: &[u8]) -> GLuint {
         let mut texture = 0;
         unsafe {
             gl::GenTextures(1, &mut texture);
-            self.textures.push(texture);
-            self.textures_paths.insert(texture, path.to_owned());
             gl::BindTexture(gl::TEXTURE_2D, texture);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
 
-            // TODO handle errors
-            let texture = self.resource_loader.load_image(path).unwrap();
-            let data = texture.raw_pixels();
-
+            // rows are tightly packed, rgb images don't have to be a multiple of 4 bytes wide
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
             gl::TexImage2D(gl::TEXTURE_2D,
                            0,
-                           gl::RGB as i32,
-                           texture.width() as i32,
-                           texture.height() as i32,
+                           internal_format as i32,
+                           width as i32,
+                           height as i32,
                            0,
-                           gl::RGB,
+                           format,
                            gl::UNSIGNED_BYTE,
                            &data[0] as *const u8 as *const c_void);
             gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        }
+        texture
+    }
+
+    pub fn load_from_gltf(&mut self, path: &str) -> Result<MultiPartModel, String> {
+        if let Some(model) = self.cached_multi_part_model(path) {
+            return Ok(model);
+        }
+        let gltf_path = self.resource_loader.to_real_path(Path::new(path));
+        let (document, buffers, images) = gltf::import(gltf_path.as_path())
+            .map_err(|e| format!("Unable to load gltf {}: {:?}", path, e))?;
+        let primitives = Loader::read_gltf(&document, &buffers, path)?;
+
+        // images are decoded by the importer whether they are embedded, in a glb or next to the file,
+        // they belong to the model so are released along with its meshes
+        let mut resources = Vec::new();
+        let mut textures = Vec::with_capacity(images.len());
+        for image in images.iter() {
+            let texture = Loader::load_gltf_image(image)?;
+            resources.push(GpuResource::Texture(texture));
+            textures.push(texture);
         }
-        return texture;
+
+        let mut parts = Vec::with_capacity(primitives.len());
+        for primitive in primitives {
+            let raw_model = self.store_mesh(&primitive.positions, &primitive.texture_coords, &primitive.normals, &primitive.indices, &mut resources);
+            let (texture_id, asset) = match primitive.image {
+                Some(image) => (textures[image], None),
+                None => {
+                    let (texture_id, asset) = self.load_texture(Loader::DEFAULT_TEXTURE);
+                    (texture_id, Some(asset))
+                }
+            };
+            parts.push(ModelPart { raw_model, texture: SimpleTexture { texture_id, asset, ..primitive.texture } });
+        }
+        Ok(self.cache_multi_part_model(path, parts, resources))
+    }
+
+    /// The primitives of the default scene, or the first if none is marked, flattened out of the node tree
+    fn read_gltf(document: &gltf::Document, buffers: &[gltf::buffer::Data], path: &str) -> Result<Vec<GltfPrimitive>, String> {
+        let scene = match document.default_scene().or_else(|| document.scenes().next()) {
+            Some(scene) => scene,
+            None => return Err(format!("No scene in gltf {}", path))
+        };
+        let mut primitives = Vec::new();
+        for node in scene.nodes() {
+            Loader::read_gltf_node(&node, &Matrix4::identity(), buffers, &mut primitives)?;
+        }
+        Ok(primitives)
+    }
+
+    fn read_gltf_node(node: &gltf::Node, parent_transform: &Matrix4<f32>, buffers: &[gltf::buffer::Data],
+                      primitives: &mut Vec<GltfPrimitive>) -> Result<(), String> {
+        let transform = *parent_transform * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                primitives.push(Loader::read_gltf_primitive(&primitive, &transform, buffers)?);
+            }
+        }
+        for child in node.children() {
+            Loader::read_gltf_node(&child, &transform, buffers, primitives)?;
+        }
+        Ok(())
+    }
+
+    fn read_gltf_primitive(primitive: &gltf::Primitive, transform: &Matrix4<f32>, buffers: &[gltf::buffer::Data]) -> Result<GltfPrimitive, String> {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return Err(format!("Unsupported gltf primitive mode {:?}", primitive.mode()));
+        }
+        let reader = primitive.reader(|buffer| Some(&*buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(positions) => positions.collect(),
+            None => return Err("gltf primitive has no positions".to_owned())
+        };
+        let normals: Vec<[f32; 3]> = match reader.read_normals() {
+            Some(normals) => normals.collect(),
+            None => vec![[0.0, 0.0, 0.0]; positions.len()]
+        };
+        let texture_coords: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+            Some(texture_coords) => texture_coords.into_f32().collect(),
+            None => vec![[0.0, 0.0]; positions.len()]
+        };
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect()
+        };
+
+        // bake the node transform into the vertices so parts only need the entity transform when drawn
+        let normal_matrix = transform.invert().unwrap_or(Matrix4::identity()).transpose();
+        let mut flat_positions = Vec::with_capacity(positions.len() * 3);
+        for position in positions.iter() {
+            let position = *transform * Vector4::new(position[0], position[1], position[2], 1.0);
+            flat_positions.extend_from_slice(&[position.x, position.y, position.z]);
+        }
+        let mut flat_normals = Vec::with_capacity(normals.len() * 3);
+        for normal in normals.iter() {
+            let mut normal = (normal_matrix * Vector4::new(normal[0], normal[1], normal[2], 0.0)).truncate();
+            if normal.magnitude2() > 0.0 {
+                normal = normal.normalize();
+            }
+            flat_normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+        }
+        let mut flat_texture_coords = Vec::with_capacity(texture_coords.len() * 2);
+        for texture_coord in texture_coords.iter() {
+            flat_texture_coords.extend_from_slice(texture_coord);
+        }
+
+        let (image, texture) = Loader::read_gltf_material(&primitive.material());
+        Ok(GltfPrimitive {
+            positions: flat_positions,
+            texture_coords: flat_texture_coords,
+            normals: flat_normals,
+            indices,
+            image,
+            texture
+        })
+    }
+
+    // approximate the metallic/roughness material with our specular model, the texture is filled in on upload
+    fn read_gltf_material(material: &gltf::Material) -> (Option<usize>, SimpleTexture) {
+        let pbr = material.pbr_metallic_roughness();
+        let base_colour = pbr.base_color_factor();
+        let roughness = pbr.roughness_factor().max(0.05);
+        let image = pbr.base_color_texture().map(|info| info.texture().source().index());
+        let texture = SimpleTexture {
+            reflectivity: 1.0 - roughness,
+            shine_damper: (2.0 / roughness.powi(4) - 2.0).clamp(1.0, 256.0),
+            diffuse_colour: Vector3::new(base_colour[0], base_colour[1], base_colour[2]),
+            has_transparency: material.alpha_mode() != gltf::material::AlphaMode::Opaque || material.double_sided(),
+            ..SimpleTexture::default()
+        };
+        (image, texture)
+    }
+
+    fn load_gltf_image(image: &gltf::image::Data) -> Result<GLuint, String> {
+        use super::gltf::image::Format;
+        let (internal_format, format) = match image.format {
+            Format::R8 => (gl::RED, gl::RED),
+            Format::R8G8 => (gl::RG, gl::RG),
+            Format::R8G8B8 => (gl::RGB, gl::RGB),
+            Format::R8G8B8A8 => (gl::RGBA, gl::RGBA),
+            Format::B8G8R8 => (gl::RGB, gl::BGR),
+            Format::B8G8R8A8 => (gl::RGBA, gl::BGRA),
+            format => return Err(format!("Unsupported gltf image format {:?}", format))
+        };
+        Ok(Loader::upload_texture(image.width, image.height, internal_format, format, &image.pixels))
     }
 
     pub fn load_cube_map(&mut self, cube_map_def: &CubeMapDefinition) -> Texture {
         let mut texture = 0;
         unsafe {
             gl::GenTextures(1, &mut texture);
-            self.textures.push(texture);
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture);
 
@@ -143,7 +518,8 @@ impl Loader {
             gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
             gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
         }
-        Texture{texture_id: texture}
+        let asset = self.create_asset(vec![GpuResource::Texture(texture)]);
+        Texture{texture_id: texture, asset: Some(asset)}
     }
 
     fn load_cube_map_face(&mut self, path: &str, face: u32) {
@@ -166,17 +542,17 @@ impl Loader {
         }
     }
 
-    fn bind_indices_buffer(&mut self, indices: &[u32]) {
+    fn bind_indices_buffer(indices: &[u32]) -> GLuint {
         let mut vbo_id = 0;
         unsafe {
             gl::GenBuffers(1, &mut vbo_id);
-            self.vbos.push(vbo_id);
             gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, vbo_id);
             gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
-                (indices.len() * mem::size_of::<GLuint>()) as GLsizeiptr,
+                mem::size_of_val(indices) as GLsizeiptr,
                 &indices[0] as *const u32 as *const c_void,
                 gl::STATIC_DRAW);
         }
+        vbo_id
     }
 
     fn create_vao() -> GLuint {
@@ -185,7 +561,7 @@ impl Loader {
             gl::GenVertexArrays(1, &mut vao_id);
             gl::BindVertexArray(vao_id);
         }
-        return vao_id;
+        vao_id
     }
 
     fn unbind_vao() {
@@ -194,34 +570,145 @@ impl Loader {
         }
     }
 
-    fn store_data_in_attribute_list(&mut self, attribute_id: GLuint, coordinate_size: i32, data: &[f32]) {
+    fn store_data_in_attribute_list(attribute_id: GLuint, coordinate_size: i32, data: &[f32]) -> GLuint {
         let mut vbo_id = 0;
         unsafe {
             gl::GenBuffers(1, &mut vbo_id);
-            self.vbos.push(vbo_id);
             gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
             gl::BufferData(gl::ARRAY_BUFFER,
-                           (data.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           mem::size_of_val(data) as GLsizeiptr,
                            &data[0] as *const f32 as *const c_void,
                            gl::STATIC_DRAW);
             gl::VertexAttribPointer(attribute_id, coordinate_size, gl::FLOAT, gl::FALSE, coordinate_size * mem::size_of::<GLfloat>() as GLsizei, ptr::null());
             gl::EnableVertexAttribArray(attribute_id);
             gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         }
+        vbo_id
     }
 }
 
 impl Drop for Loader {
     fn drop(&mut self) {
-        for vao_id in &self.vaos {
-            // check first parameter
-            unsafe { gl::DeleteVertexArrays(1, vao_id); }
+        self.release_unused();
+        // anything still referenced goes with the loader
+        let remaining: Vec<GpuResource> = self.live_resources.iter().cloned().collect();
+        for resource in remaining {
+            self.delete_resource(resource);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a triangle under a translated node, and again under a scaled child node with a textured,
+    // blended material and no indices
+    const NESTED_GLTF: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0]}],
+        "nodes": [
+            {"translation": [10.0, 0.0, 0.0], "mesh": 0, "children": [1]},
+            {"scale": [2.0, 2.0, 2.0], "mesh": 1}
+        ],
+        "meshes": [
+            {"primitives": [{"attributes": {"POSITION": 0}, "indices": 1}]},
+            {"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}
+        ],
+        "materials": [{
+            "pbrMetallicRoughness": {"baseColorTexture": {"index": 0}, "baseColorFactor": [0.5, 0.25, 1.0, 1.0], "roughnessFactor": 0.5},
+            "alphaMode": "BLEND"
+        }],
+        "textures": [{"source": 0}],
+        "images": [{"uri": "missing.png"}],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0]},
+            {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+        ],
+        "bufferViews": [
+            {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+        ],
+        "buffers": [{"byteLength": 44, "uri": "triangle.bin"}]
+    }"#;
+
+    // the buffer the gltf refers to, the triangle's positions followed by its indices
+    fn triangle_buffer() -> gltf::buffer::Data {
+        let mut data = Vec::new();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2, 0].iter() {
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+        gltf::buffer::Data(data)
+    }
+
+    fn read(source: &str) -> Result<Vec<GltfPrimitive>, String> {
+        let document = gltf::Gltf::from_slice(source.as_bytes()).unwrap().document;
+        Loader::read_gltf(&document, &[triangle_buffer()], "test.gltf")
+    }
+
+    fn test_loader() -> Loader {
+        Loader::new(Rc::new(ResourceLoader::from_relative_path(Path::new("no-such-res")).unwrap()))
+    }
+
+    #[test]
+    fn nested_nodes_are_flattened_with_their_transforms() {
+        let primitives = read(NESTED_GLTF).unwrap();
+        assert_eq!(primitives.len(), 2);
+
+        assert_eq!(primitives[0].positions, vec![10.0, 0.0, 0.0, 11.0, 0.0, 0.0, 10.0, 1.0, 0.0]);
+        assert_eq!(primitives[0].indices, vec![0, 1, 2]);
+        assert_eq!(primitives[1].positions, vec![10.0, 0.0, 0.0, 12.0, 0.0, 0.0, 10.0, 2.0, 0.0]);
+        // unindexed primitives draw their vertices in order
+        assert_eq!(primitives[1].indices, vec![0, 1, 2]);
+
+        for primitive in primitives.iter() {
+            assert_eq!(primitive.normals, vec![0.0; 9]);
+            assert_eq!(primitive.texture_coords, vec![0.0; 6]);
         }
-        for vbo_id in &self.vbos {
-            unsafe { gl::DeleteBuffers(1, vbo_id); }
+    }
+
+    #[test]
+    fn primitives_without_a_material_fall_back_to_the_default_texture() {
+        let primitives = read(NESTED_GLTF).unwrap();
+        let texture = &primitives[0].texture;
+        assert_eq!(primitives[0].image, None);
+        assert_eq!(texture.reflectivity, 0.0);
+        assert_eq!(texture.shine_damper, 1.0);
+        assert_eq!(texture.diffuse_colour, Vector3::new(1.0, 1.0, 1.0));
+        assert!(!texture.has_transparency);
+    }
+
+    #[test]
+    fn materials_pick_their_image_and_specular_approximation() {
+        let primitives = read(NESTED_GLTF).unwrap();
+        let texture = &primitives[1].texture;
+        assert_eq!(primitives[1].image, Some(0));
+        assert_eq!(texture.reflectivity, 0.5);
+        assert_eq!(texture.shine_damper, 30.0);
+        assert_eq!(texture.diffuse_colour, Vector3::new(0.5, 0.25, 1.0));
+        assert!(texture.has_transparency);
+    }
+
+    #[test]
+    fn a_gltf_without_scenes_is_an_error() {
+        match read(r#"{"asset": {"version": "2.0"}}"#) {
+            Err(message) => assert_eq!(message, "No scene in gltf test.gltf"),
+            Ok(_) => panic!("expected an error")
         }
-        for texture in &self.textures {
-            unsafe { gl::DeleteTextures(1, texture); }
+    }
+
+    #[test]
+    fn multi_part_models_are_loaded_by_extension() {
+        let mut loader = test_loader();
+        for path in ["models/missing.gltf", "models/missing.glb"].iter() {
+            let message = loader.load_multi_part_model(path).err().unwrap();
+            assert!(message.starts_with("Unable to load gltf"), "{}", message);
         }
+        let message = loader.load_multi_part_model("models/missing.obj").err().unwrap();
+        assert!(message.starts_with("Unable to load obj"), "{}", message);
     }
 }
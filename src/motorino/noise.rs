@@ -0,0 +1,166 @@
+use super::terrain::HeightSource;
+
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseConfig {
+    pub seed: u32,
+    pub octaves: u32,
+    /// amplitude multiplier from one octave to the next
+    pub persistence: f32,
+    /// frequency multiplier from one octave to the next
+    pub lacunarity: f32,
+    /// world units covered by one cell of the first octave
+    pub scale: f32
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        NoiseConfig {
+            seed: 0,
+            octaves: 5,
+            persistence: 0.5,
+            lacunarity: 2.0,
+            scale: 300.0
+        }
+    }
+}
+
+/// Fractal Perlin noise, the same seed always gives the same landscape
+pub struct NoiseHeightMap {
+    config: NoiseConfig,
+    permutation: Vec<usize>
+}
+
+impl NoiseHeightMap {
+    const PERMUTATION_SIZE: usize = 256;
+
+    pub fn new(config: NoiseConfig) -> NoiseHeightMap {
+        NoiseHeightMap { config, permutation: NoiseHeightMap::create_permutation(config.seed) }
+    }
+
+    // shuffled with a fixed xorshift rather than rand so a seed keeps giving the same terrain
+    // whatever version of rand is in use
+    fn create_permutation(seed: u32) -> Vec<usize> {
+        let mut state = seed ^ 0x9E37_79B9;
+        if state == 0 {
+            state = 1;
+        }
+        let mut permutation: Vec<usize> = (0..NoiseHeightMap::PERMUTATION_SIZE).collect();
+        for i in (1..NoiseHeightMap::PERMUTATION_SIZE).rev() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let j = state as usize % (i + 1);
+            permutation.swap(i, j);
+        }
+        // doubled so lookups of index + 1 don't need wrapping
+        let doubled = permutation.clone();
+        permutation.extend(doubled);
+        permutation
+    }
+
+    /// Sum of the octaves at a world position, roughly in -1..1
+    pub fn fractal(&self, x: f32, z: f32) -> f32 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0 / self.config.scale;
+        let mut max_amplitude = 0.0;
+        for _ in 0..self.config.octaves {
+            total += self.perlin(x * frequency, z * frequency) * amplitude;
+            max_amplitude += amplitude;
+            amplitude *= self.config.persistence;
+            frequency *= self.config.lacunarity;
+        }
+        if max_amplitude > 0.0 { total / max_amplitude } else { 0.0 }
+    }
+
+    fn perlin(&self, x: f32, z: f32) -> f32 {
+        let x_floor = x.floor();
+        let z_floor = z.floor();
+        let xi = (x_floor as i32 & 255) as usize;
+        let zi = (z_floor as i32 & 255) as usize;
+        let xf = x - x_floor;
+        let zf = z - z_floor;
+        let u = NoiseHeightMap::fade(xf);
+        let v = NoiseHeightMap::fade(zf);
+
+        let p = &self.permutation;
+        let aa = p[p[xi] + zi];
+        let ab = p[p[xi] + zi + 1];
+        let ba = p[p[xi + 1] + zi];
+        let bb = p[p[xi + 1] + zi + 1];
+
+        let x1 = NoiseHeightMap::lerp(NoiseHeightMap::gradient(aa, xf, zf), NoiseHeightMap::gradient(ba, xf - 1.0, zf), u);
+        let x2 = NoiseHeightMap::lerp(NoiseHeightMap::gradient(ab, xf, zf - 1.0), NoiseHeightMap::gradient(bb, xf - 1.0, zf - 1.0), u);
+        NoiseHeightMap::lerp(x1, x2, v)
+    }
+
+    fn gradient(hash: usize, x: f32, z: f32) -> f32 {
+        match hash & 7 {
+            0 => x + z,
+            1 => -x + z,
+            2 => x - z,
+            3 => -x - z,
+            4 => x,
+            5 => -x,
+            6 => z,
+            _ => -z
+        }
+    }
+
+    fn fade(t: f32) -> f32 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    fn lerp(a: f32, b: f32, t: f32) -> f32 {
+        a + t * (b - a)
+    }
+}
+
+impl HeightSource for NoiseHeightMap {
+    // sampled in world space so neighbouring tiles line up
+    fn get_height(&self, _u: f32, _v: f32, world_x: f32, world_z: f32) -> f32 {
+        (self.fractal(world_x, world_z) * 0.5 + 0.5).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(seed: u32) -> NoiseHeightMap {
+        NoiseHeightMap::new(NoiseConfig { seed, ..NoiseConfig::default() })
+    }
+
+    fn samples(noise: &NoiseHeightMap) -> Vec<f32> {
+        (0..64).map(|i| noise.fractal(i as f32 * 37.3, i as f32 * -21.9)).collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_heights() {
+        assert_eq!(samples(&noise(42)), samples(&noise(42)));
+    }
+
+    #[test]
+    fn different_seeds_give_different_heights() {
+        assert_ne!(samples(&noise(42)), samples(&noise(43)));
+    }
+
+    #[test]
+    fn permutation_is_a_doubled_shuffle() {
+        let permutation = NoiseHeightMap::create_permutation(7);
+        let (first, second) = permutation.split_at(NoiseHeightMap::PERMUTATION_SIZE);
+        assert_eq!(first, second);
+        let mut sorted = first.to_vec();
+        sorted.sort();
+        assert_eq!(sorted, (0..NoiseHeightMap::PERMUTATION_SIZE).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn heights_stay_in_range() {
+        let noise = noise(0);
+        for i in 0..256 {
+            let height = noise.get_height(0.0, 0.0, i as f32 * 13.7, i as f32 * 5.1);
+            assert!((0.0..=1.0).contains(&height));
+        }
+    }
+}
@@ -13,18 +13,33 @@ use super::terrain::Terrain;
 use super::components::GridPosition;
 use super::components::TerrainPhysics;
 
+/// How the player moves, set from the engine config
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerSettings {
+    pub run_speed: f32,
+    /// degrees per second
+    pub turn_speed: f32,
+    pub gravity: f32,
+    pub jump_power: f32
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            run_speed: 50.0,
+            turn_speed: 120.0,
+            gravity: -80.0,
+            jump_power: 25.0
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct PlayerController {
     pub is_in_air: bool
 }
 
-impl PlayerController {
-    const RUN_SPEED: f32 = 50.0;
-    const TURN_SPEED: f32 = 120.0;
-    const GRAVITY: f32 = -80.0;
-    const JUMP_POWER: f32 = 25.0;
-}
-
 impl<'a> System<'a> for PlayerController {
     type SystemData = (ReadStorage<'a, PlayerFlag>,
                        WriteStorage<'a, Velocity>,
@@ -32,64 +47,61 @@ impl<'a> System<'a> for PlayerController {
                        ReadStorage<'a, GridPosition>,
                        ReadStorage<'a, TerrainPhysics>,
                        Read<'a, KeyEvents>,
+                       Read<'a, PlayerSettings>,
                        Read<'a, UpdateDeltaTime>);
 
-    fn run(&mut self, (_player_flag, mut velocity, mut transform, grid_position, terrain_physics, key_events, dt): <Self as System<'a>>::SystemData) {
+    fn run(&mut self, (_player_flag, mut velocity, mut transform, grid_position, terrain_physics, key_events, player_settings, dt): <Self as System<'a>>::SystemData) {
         let dt = dt.0;
         use super::specs::Join;
-        for (mut velocity, mut transform) in (&mut velocity, &mut transform).join() {
+        for (velocity, transform) in (&mut velocity, &mut transform).join() {
             for key_event in key_events.iter() {
                 let key = &key_event.source;
                 let action = &key_event.action;
                 if key == &Key::W {
                     if action == &Action::Press || action == &Action::Repeat {
-                        velocity.run_velocity = PlayerController::RUN_SPEED;
+                        velocity.run_velocity = player_settings.run_speed;
                      } else if action == &Action::Release {
                         velocity.run_velocity = 0.0;
                     }
                 }
                 if key == &Key::S {
                     if action == &Action::Press || action == &Action::Repeat {
-                        velocity.run_velocity = -PlayerController::RUN_SPEED;
+                        velocity.run_velocity = -player_settings.run_speed;
                     } else if action == &Action::Release {
                         velocity.run_velocity = 0.0;
                     }
                 }
                 if key == &Key::A {
                     if action == &Action::Press || action == &Action::Repeat {
-                        velocity.turn_velocity = PlayerController::TURN_SPEED;
+                        velocity.turn_velocity = player_settings.turn_speed;
                     } else if action == &Action::Release {
                         velocity.turn_velocity = 0.0;
                     }
                 }
                 if key == &Key::D {
                     if action == &Action::Press || action == &Action::Repeat {
-                        velocity.turn_velocity = -PlayerController::TURN_SPEED;
+                        velocity.turn_velocity = -player_settings.turn_speed;
                     } else if action == &Action::Release {
                         velocity.turn_velocity = 0.0;
                     }
                 }
-                if key == &Key::Space {
-                    if action == &Action::Press || action == &Action::Repeat {
-                        if !self.is_in_air {
-                            velocity.upwards_velocity =  PlayerController::JUMP_POWER;
-                            self.is_in_air = true;
-                        }
-                    }
+                if key == &Key::Space && (action == &Action::Press || action == &Action::Repeat) && !self.is_in_air {
+                    velocity.upwards_velocity =  player_settings.jump_power;
+                    self.is_in_air = true;
                 }
             }
 
-            transform.rotation.y = transform.rotation.y + velocity.turn_velocity * dt;
+            transform.rotation.y += velocity.turn_velocity * dt;
             let rotation_rad = PI as f32 / 180.0 * transform.rotation.y;
             let dz = velocity.run_velocity * dt * rotation_rad.sin();
             let dx = velocity.run_velocity * dt * rotation_rad.cos();
-            transform.position.x = transform.position.x + dz;
-            transform.position.z = transform.position.z + dx;
+            transform.position.x += dz;
+            transform.position.z += dx;
 
             let height = Terrain::get_height_for_position(&grid_position, &terrain_physics, transform.position.x, transform.position.z);
 
-            velocity.upwards_velocity = velocity.upwards_velocity + PlayerController::GRAVITY * dt;
-            transform.position.y = transform.position.y + velocity.upwards_velocity * dt;
+            velocity.upwards_velocity += player_settings.gravity * dt;
+            transform.position.y += velocity.upwards_velocity * dt;
 
             if transform.position.y < height {
                 velocity.upwards_velocity = 0.0;
@@ -0,0 +1,335 @@
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use super::debugui::DebugInfo;
+use super::gl;
+use super::gl::types::*;
+use super::renderers::{Attachment, Fbo, RenderSettings, RenderTarget, ScenePass};
+use super::resources::ResourceLoader;
+use super::shaders::ShaderProgram;
+use super::specs::{Read, System, Write};
+use super::WindowSize;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PostProcessingSettings {
+    /// draw the scene into a high dynamic range buffer and run the effects below on it
+    pub enabled: bool,
+    /// samples per pixel the scene is drawn with, 1 for no multisampling
+    pub msaa_samples: i32,
+    pub bloom: bool,
+    /// brightness above which pixels glow
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub tone_mapping: bool,
+    pub exposure: f32,
+    pub gamma_correction: bool,
+    pub gamma: f32,
+    pub fxaa: bool
+}
+
+impl Default for PostProcessingSettings {
+    fn default() -> Self {
+        PostProcessingSettings {
+            enabled: true,
+            msaa_samples: 4,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.6,
+            // the textures are authored for the screen as is, so these change the look
+            tone_mapping: false,
+            exposure: 1.0,
+            gamma_correction: false,
+            gamma: 2.2,
+            fxaa: true
+        }
+    }
+}
+
+/// The high dynamic range scene drawn this frame, none while post-processing is off
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HdrScene {
+    pub texture_id: GLuint
+}
+
+/// Points the scene renderers that follow it at the high dynamic range scene buffer. With
+/// multisampling on they draw into a multisampled buffer instead, which `resolve` copies into the
+/// scene texture once the scene is drawn.
+pub struct HdrScenePass {
+    fbo: Fbo,
+    multisampled_fbo: Option<Fbo>,
+    // a sample count the driver couldn't make a buffer with, not tried again until it changes
+    failed_samples: Option<u32>
+}
+
+impl HdrScenePass {
+    const COLOUR_FORMAT: GLenum = gl::RGBA16F;
+
+    pub fn new(window_size: &WindowSize) -> Result<HdrScenePass, String> {
+        // floating point colour so lights brighter than white survive until tone mapping
+        Ok(HdrScenePass {
+            fbo: Fbo::new(window_size.width, window_size.height, Attachment::Texture(HdrScenePass::COLOUR_FORMAT), Attachment::RenderBuffer(gl::DEPTH_COMPONENT24))?,
+            multisampled_fbo: None,
+            failed_samples: None
+        })
+    }
+
+    /// Averages the samples of the multisampled scene into the scene texture the post-processing reads
+    pub fn resolve(&self, window_size: &WindowSize) {
+        if let Some(ref multisampled_fbo) = self.multisampled_fbo {
+            multisampled_fbo.resolve(&self.fbo.target(), window_size);
+        }
+    }
+
+    // made again whenever the sample count changes, the scene is drawn without multisampling if it
+    // can't be made
+    fn update_multisampled_fbo(&mut self, samples: u32, window_size: &WindowSize) -> Result<(), String> {
+        if let Some(ref mut fbo) = self.multisampled_fbo {
+            if fbo.samples() == samples {
+                return fbo.resize(window_size.width, window_size.height);
+            }
+        }
+        self.multisampled_fbo = None;
+        if samples <= 1 || self.failed_samples == Some(samples) {
+            return Ok(());
+        }
+        match Fbo::multisampled(window_size.width, window_size.height, samples, HdrScenePass::COLOUR_FORMAT) {
+            Ok(fbo) => {
+                self.multisampled_fbo = Some(fbo);
+                self.failed_samples = None;
+                Ok(())
+            },
+            Err(message) => {
+                self.failed_samples = Some(samples);
+                Err(message)
+            }
+        }
+    }
+}
+
+impl<'a> System<'a> for HdrScenePass {
+    type SystemData = (Read<'a, RenderSettings>,
+                       Read<'a, WindowSize>,
+                       Write<'a, ScenePass>,
+                       Write<'a, HdrScene>,
+                       Write<'a, DebugInfo>);
+
+    fn run(&mut self, (render_settings, window_size, mut scene_pass, mut hdr_scene, mut debug_info): Self::SystemData) {
+        if render_settings.post_processing.enabled {
+            let resized = self.fbo.resize(window_size.width, window_size.height)
+                .and_then(|_| self.update_multisampled_fbo(render_settings.post_processing.msaa_samples.max(1) as u32, &window_size));
+            if let Err(message) = resized {
+                debug_info.framebuffer_error = Some(message);
+            }
+            scene_pass.target = match self.multisampled_fbo {
+                Some(ref multisampled_fbo) => multisampled_fbo.target(),
+                None => self.fbo.target()
+            };
+            hdr_scene.texture_id = self.fbo.colour_texture();
+        } else {
+            self.multisampled_fbo = None;
+            scene_pass.target = RenderTarget::Screen;
+            hdr_scene.texture_id = 0;
+        }
+    }
+}
+
+/// Draws the `HdrScene` to the screen through full-screen quads: bloom, tone mapping,
+/// gamma correction and anti-aliasing, each as enabled in `PostProcessingSettings`
+pub struct PostProcessingRenderer {
+    bloom_extract_shader: ShaderProgram,
+    bloom_blur_shader: ShaderProgram,
+    tone_mapping_shader: ShaderProgram,
+    fxaa_shader: ShaderProgram,
+    quad_vao: GLuint,
+    quad_vbo: GLuint,
+    // blurred back and forth between the two, at half resolution as it is blurry anyway
+    bloom_fbos: [Fbo; 2],
+    // the tone mapped scene, when it still has to be anti-aliased
+    ldr_fbo: Fbo
+}
+
+impl<'a> System<'a> for PostProcessingRenderer {
+    type SystemData = (Read<'a, RenderSettings>,
+                       Read<'a, HdrScene>,
+                       Read<'a, WindowSize>,
+                       Write<'a, DebugInfo>);
+
+    fn run(&mut self, (render_settings, hdr_scene, window_size, mut debug_info): Self::SystemData) {
+        if hdr_scene.texture_id == 0 {
+            return;
+        }
+        let settings = &render_settings.post_processing;
+        if let Err(message) = self.resize(&window_size) {
+            debug_info.framebuffer_error = Some(message);
+        }
+        let blend_enabled = self.prepare();
+
+        let bloom_texture = if settings.bloom {
+            self.bloom(hdr_scene.texture_id, settings, &mut debug_info)
+        } else {
+            0
+        };
+
+        let output = if settings.fxaa { self.ldr_fbo.target() } else { RenderTarget::Screen };
+        output.bind(&window_size);
+        self.tone_mapping_shader.start();
+        unsafe {
+            self.tone_mapping_shader.set_float(c_str!("use_bloom"), if settings.bloom { 1.0 } else { 0.0 });
+            self.tone_mapping_shader.set_float(c_str!("bloom_intensity"), settings.bloom_intensity);
+            self.tone_mapping_shader.set_float(c_str!("use_tone_mapping"), if settings.tone_mapping { 1.0 } else { 0.0 });
+            self.tone_mapping_shader.set_float(c_str!("exposure"), settings.exposure);
+            self.tone_mapping_shader.set_float(c_str!("use_gamma_correction"), if settings.gamma_correction { 1.0 } else { 0.0 });
+            self.tone_mapping_shader.set_float(c_str!("gamma"), settings.gamma);
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, bloom_texture);
+        }
+        self.draw_quad(hdr_scene.texture_id, &mut debug_info);
+        self.tone_mapping_shader.stop();
+
+        if settings.fxaa {
+            RenderTarget::Screen.bind(&window_size);
+            self.fxaa_shader.start();
+            self.draw_quad(self.ldr_fbo.colour_texture(), &mut debug_info);
+            self.fxaa_shader.stop();
+        }
+        self.finish(blend_enabled);
+    }
+}
+
+impl PostProcessingRenderer {
+
+    const VERTEX_SHADER: &'static str = "shaders/post-processing.vert";
+    const BLOOM_EXTRACT_SHADER: &'static str = "shaders/bloom-extract.frag";
+    const BLOOM_BLUR_SHADER: &'static str = "shaders/bloom-blur.frag";
+    const TONE_MAPPING_SHADER: &'static str = "shaders/tone-mapping.frag";
+    const FXAA_SHADER: &'static str = "shaders/fxaa.frag";
+    // each a horizontal and a vertical pass
+    const BLUR_PASSES: u32 = 3;
+
+    pub fn new(resource_loader: &ResourceLoader, window_size: &WindowSize) -> Result<PostProcessingRenderer, String> {
+        let shader = |fragment_shader: &str| ShaderProgram::from_shader_files(resource_loader, PostProcessingRenderer::VERTEX_SHADER, fragment_shader)
+            .map_err(|e| e.to_string());
+        let bloom_extract_shader = shader(PostProcessingRenderer::BLOOM_EXTRACT_SHADER)?;
+        let bloom_blur_shader = shader(PostProcessingRenderer::BLOOM_BLUR_SHADER)?;
+        let tone_mapping_shader = shader(PostProcessingRenderer::TONE_MAPPING_SHADER)?;
+        let fxaa_shader = shader(PostProcessingRenderer::FXAA_SHADER)?;
+
+        const quad_positions: [f32; 8] = [-1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0, -1.0];
+        let mut quad_vao = 0;
+        let mut quad_vbo = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut quad_vao);
+            gl::BindVertexArray(quad_vao);
+            gl::GenBuffers(1, &mut quad_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, quad_vbo);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (quad_positions.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           &quad_positions[0] as *const f32 as *const c_void,
+                           gl::STATIC_DRAW);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 0, ptr::null());
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        let bloom_fbo = || Fbo::new(window_size.width / 2, window_size.height / 2, Attachment::Texture(gl::RGBA16F), Attachment::None);
+        let bloom_fbos = [bloom_fbo()?, bloom_fbo()?];
+        let ldr_fbo = Fbo::new(window_size.width, window_size.height, Attachment::Texture(gl::RGBA8), Attachment::None)?;
+
+        Ok(PostProcessingRenderer {
+            bloom_extract_shader, bloom_blur_shader, tone_mapping_shader, fxaa_shader, quad_vao, quad_vbo, bloom_fbos, ldr_fbo
+        })
+    }
+
+    fn bind_samplers(&self) {
+        unsafe {
+            self.bloom_extract_shader.start();
+            self.bloom_extract_shader.set_int(c_str!("scene_sampler"), 0);
+            self.bloom_blur_shader.start();
+            self.bloom_blur_shader.set_int(c_str!("bloom_sampler"), 0);
+            self.tone_mapping_shader.start();
+            self.tone_mapping_shader.set_int(c_str!("scene_sampler"), 0);
+            self.tone_mapping_shader.set_int(c_str!("bloom_sampler"), 1);
+            self.fxaa_shader.start();
+            self.fxaa_shader.set_int(c_str!("scene_sampler"), 0);
+            self.fxaa_shader.stop();
+        }
+    }
+
+    /// Follows the screen when the window is resized
+    fn resize(&mut self, window_size: &WindowSize) -> Result<(), String> {
+        for bloom_fbo in self.bloom_fbos.iter_mut() {
+            bloom_fbo.resize(window_size.width / 2, window_size.height / 2)?;
+        }
+        self.ldr_fbo.resize(window_size.width, window_size.height)
+    }
+
+    /// Returns the texture holding the blurred bright parts of the scene
+    fn bloom(&self, scene_texture: GLuint, settings: &PostProcessingSettings, debug_info: &mut DebugInfo) -> GLuint {
+        self.bloom_fbos[0].bind();
+        self.bloom_extract_shader.start();
+        unsafe {
+            self.bloom_extract_shader.set_float(c_str!("bloom_threshold"), settings.bloom_threshold);
+        }
+        self.draw_quad(scene_texture, debug_info);
+        self.bloom_extract_shader.stop();
+
+        self.bloom_blur_shader.start();
+        for pass in 0..PostProcessingRenderer::BLUR_PASSES * 2 {
+            let source = &self.bloom_fbos[(pass % 2) as usize];
+            let target = &self.bloom_fbos[((pass + 1) % 2) as usize];
+            target.bind();
+            unsafe {
+                self.bloom_blur_shader.set_float(c_str!("horizontal"), if pass % 2 == 0 { 1.0 } else { 0.0 });
+            }
+            self.draw_quad(source.colour_texture(), debug_info);
+        }
+        self.bloom_blur_shader.stop();
+        // an even number of passes ends up back in the first
+        self.bloom_fbos[0].colour_texture()
+    }
+
+    /// Returns whether blending was on, for `finish` to put back
+    fn prepare(&self) -> bool {
+        // every frame, as a reloaded program starts with all samplers on unit 0
+        self.bind_samplers();
+        unsafe {
+            let blend_enabled = gl::IsEnabled(gl::BLEND) == gl::TRUE;
+            gl::Disable(gl::DEPTH_TEST);
+            gl::Disable(gl::BLEND);
+            gl::BindVertexArray(self.quad_vao);
+            gl::EnableVertexAttribArray(0); // positions
+            blend_enabled
+        }
+    }
+
+    fn draw_quad(&self, texture: GLuint, debug_info: &mut DebugInfo) {
+        debug_info.current_frame_draw_calls += 1;
+        debug_info.current_frame_triangle_count += 2;
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        }
+    }
+
+    fn finish(&self, blend_enabled: bool) {
+        unsafe {
+            gl::DisableVertexAttribArray(0);
+            gl::BindVertexArray(0);
+            gl::Enable(gl::DEPTH_TEST);
+            if blend_enabled {
+                gl::Enable(gl::BLEND);
+            }
+        }
+    }
+}
+
+impl Drop for PostProcessingRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.quad_vao);
+            gl::DeleteBuffers(1, &self.quad_vbo);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::ffi::CString;
+use super::shaders::{ShaderError, SourceMap};
+
+/// A shader source with its `#include`s pasted in and the `#define`s it was asked for added
+pub struct PreprocessedSource {
+    pub source: CString,
+    pub source_map: SourceMap,
+    /// the file itself followed by everything it includes, which all need watching for changes
+    pub files: Vec<String>
+}
+
+/// Resolves `#include "file"` relative to the file it is in, each file being pasted in only once,
+/// and adds a `#define NAME VALUE` line for each define straight after the `#version` line.
+pub fn preprocess<F>(file: &str, defines: &[(String, String)], load: &F) -> Result<PreprocessedSource, ShaderError>
+    where F: Fn(&str) -> Result<String, String>
+{
+    let mut preprocessor = Preprocessor { load, defines, defines_added: false, lines: Vec::new(), files: Vec::new() };
+    preprocessor.add_file(file)?;
+    if !preprocessor.defines_added {
+        // no #version, so the defines go first
+        let mut lines = preprocessor.define_lines(file, 1);
+        lines.append(&mut preprocessor.lines);
+        preprocessor.lines = lines;
+    }
+
+    let mut source_map = SourceMap::default();
+    let mut source = String::new();
+    for &(ref text, ref file, line) in preprocessor.lines.iter() {
+        source.push_str(text);
+        source.push('\n');
+        source_map.push(file, line);
+    }
+    let source = CString::new(source)
+        .map_err(|_| format!("Unable to convert {} to a C string", file))?;
+    Ok(PreprocessedSource { source, source_map, files: preprocessor.files })
+}
+
+struct Preprocessor<'a, F: 'a> {
+    load: &'a F,
+    defines: &'a [(String, String)],
+    defines_added: bool,
+    // each line with the file and line it came from
+    lines: Vec<(String, String, u32)>,
+    files: Vec<String>
+}
+
+impl<'a, F> Preprocessor<'a, F>
+    where F: Fn(&str) -> Result<String, String>
+{
+    fn add_file(&mut self, file: &str) -> Result<(), ShaderError> {
+        self.files.push(file.to_string());
+        let contents = (self.load)(file)?;
+        for (index, text) in contents.lines().enumerate() {
+            let line = index as u32 + 1;
+            let directive = text.trim_start();
+            if let Some(argument) = directive.strip_prefix("#include") {
+                let included = Preprocessor::<F>::include_path(file, line, argument)?;
+                if !self.files.contains(&included) {
+                    self.add_file(&included)?;
+                }
+            } else {
+                self.lines.push((text.to_string(), file.to_string(), line));
+                if !self.defines_added && directive.starts_with("#version") {
+                    let mut define_lines = self.define_lines(file, line);
+                    self.lines.append(&mut define_lines);
+                    self.defines_added = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the defines are reported against the line they follow
+    fn define_lines(&self, file: &str, line: u32) -> Vec<(String, String, u32)> {
+        self.defines.iter()
+            .map(|(name, value)| (format!("#define {} {}", name, value), file.to_string(), line))
+            .collect()
+    }
+
+    // `"lighting.glsl"` in shaders/default.frag is shaders/lighting.glsl
+    fn include_path(file: &str, line: u32, argument: &str) -> Result<String, ShaderError> {
+        let argument = argument.trim();
+        if argument.len() < 2 || !argument.starts_with('"') || !argument.ends_with('"') {
+            return Err(ShaderError::Load(format!("{}:{}: expected #include \"file\"", file, line)));
+        }
+        let name = &argument[1..argument.len() - 1];
+        Ok(match file.rfind('/') {
+            Some(directory_end) => format!("{}/{}", &file[..directory_end], name),
+            None => name.to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn preprocess_files(files: &[(&str, &str)], file: &str, defines: &[(&str, &str)]) -> Result<PreprocessedSource, ShaderError> {
+        let files: HashMap<String, String> = files.iter()
+            .map(|&(name, contents)| (name.to_string(), contents.to_string()))
+            .collect();
+        let load = |name: &str| files.get(name).cloned().ok_or(format!("Unable to read file {}", name));
+        let defines: Vec<(String, String)> = defines.iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        preprocess(file, &defines, &load)
+    }
+
+    fn lines(preprocessed: &PreprocessedSource) -> Vec<String> {
+        preprocessed.source.to_str().unwrap().lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn defines_follow_the_version() {
+        let preprocessed = preprocess_files(&[("a.frag", "// lighting\n#version 330\nvoid main() {}")], "a.frag", &[("LIGHTS", "4"), ("FOG", "1")]).ok().unwrap();
+        assert_eq!(lines(&preprocessed), vec!["// lighting", "#version 330", "#define LIGHTS 4", "#define FOG 1", "void main() {}"]);
+    }
+
+    #[test]
+    fn defines_go_first_without_a_version() {
+        let preprocessed = preprocess_files(&[("a.glsl", "float f;")], "a.glsl", &[("LIGHTS", "4")]).ok().unwrap();
+        assert_eq!(lines(&preprocessed), vec!["#define LIGHTS 4", "float f;"]);
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_including_file() {
+        let preprocessed = preprocess_files(&[
+            ("shaders/a.frag", "#version 330\n#include \"lib/light.glsl\"\nvoid main() {}"),
+            ("shaders/lib/light.glsl", "  #include \"common.glsl\"\nvec3 light();"),
+            ("shaders/lib/common.glsl", "float common;")
+        ], "shaders/a.frag", &[]).ok().unwrap();
+        assert_eq!(lines(&preprocessed), vec!["#version 330", "float common;", "vec3 light();", "void main() {}"]);
+        assert_eq!(preprocessed.files, vec!["shaders/a.frag", "shaders/lib/light.glsl", "shaders/lib/common.glsl"]);
+    }
+
+    #[test]
+    fn each_file_is_included_once() {
+        let preprocessed = preprocess_files(&[
+            ("a.frag", "#include \"b.glsl\"\n#include \"c.glsl\"\nvoid main() {}"),
+            ("b.glsl", "#include \"c.glsl\"\nfloat b;"),
+            // and including its includer again doesn't loop
+            ("c.glsl", "#include \"b.glsl\"\nfloat c;")
+        ], "a.frag", &[]).ok().unwrap();
+        assert_eq!(lines(&preprocessed), vec!["float c;", "float b;", "void main() {}"]);
+    }
+
+    #[test]
+    fn log_lines_point_back_into_the_included_files() {
+        let preprocessed = preprocess_files(&[
+            ("a.frag", "#version 330\n#include \"b.glsl\"\nvoid main() {}"),
+            ("b.glsl", "float b;\nfloat c;")
+        ], "a.frag", &[("LIGHTS", "4")]).ok().unwrap();
+        assert_eq!(preprocessed.source_map.file(), "a.frag");
+        let log = "0:2(1): error: LIGHTS redefined\n0:4(7): error: c\n0:5(1): error: main";
+        assert_eq!(preprocessed.source_map.map_log(log), "a.frag:1(1): error: LIGHTS redefined\nb.glsl:2(7): error: c\na.frag:3(1): error: main");
+    }
+
+    #[test]
+    fn malformed_includes_are_load_errors() {
+        match preprocess_files(&[("a.frag", "#version 330\n#include <b.glsl>")], "a.frag", &[]) {
+            Err(ShaderError::Load(message)) => assert!(message.starts_with("a.frag:2:")),
+            _ => panic!("expected a load error")
+        }
+    }
+
+    #[test]
+    fn missing_includes_are_load_errors() {
+        match preprocess_files(&[("a.frag", "#include \"b.glsl\"")], "a.frag", &[]) {
+            Err(ShaderError::Load(message)) => assert!(message.contains("b.glsl")),
+            _ => panic!("expected a load error")
+        }
+    }
+}
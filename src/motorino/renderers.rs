@@ -1,39 +1,599 @@
 #![macro_use]
 
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_void;
 use std::ptr;
 use super::camera::Camera;
-use super::cgmath::{Deg, Matrix4, perspective, Vector3};
-use super::components::{GridPosition, RawModel, SimpleTexture, Transform};
-use super::environment::{Fog, Light};
+use super::cgmath::{Deg, Matrix4, Vector3, Vector4};
+use super::components::{MultiPartModel, RawModel, SimpleTexture, TerrainPhysics, Transform};
 use super::gl;
+use super::gl::types::*;
 use super::resources::ResourceLoader;
+use super::frameuniforms::FrameUniformUploader;
 use super::shaders::ShaderProgram;
-use super::specs::{Read, ReadStorage, System};
+use super::specs::{Read, ReadStorage, Resources, RunNow, System};
 use super::WindowSize;
 use super::components::TerrainTexturePack;
 use super::specs::Write;
-use super::debugui::DebugInfo;
+use super::debugui::{DebugInfo, DrawStats};
+use super::frustum::Frustum;
+use super::terrain::TerrainLod;
+use super::components::BoundingBox;
+use super::shadows::ShadowMap;
+use super::postprocessing::PostProcessingSettings;
+use super::debugdraw::DebugDrawSettings;
+use super::postprocessing::HdrScenePass;
+use super::skybox::SkyboxRenderer;
+use super::water::{WaterPassRenderer, WaterRenderer};
 
-pub struct RenderConstants {
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RenderSettings {
+    pub debug_ui: bool,
+    pub render_mode: RenderMode,
+    pub post_processing: PostProcessingSettings,
+    pub debug_draw: DebugDrawSettings
 }
 
-impl RenderConstants {
-    pub const FOV: f32 = 45.0;
-    pub const NEAR_PLANE: f32 = 0.1;
-    pub const FAR_PLANE: f32 = 1500.0;
+/// Diagnostic views the entity, terrain and skybox renderers can draw in place of normal shading
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RenderMode {
+    #[default]
+    Shaded,
+    Wireframe,
+    Normals,
+    UvChecker,
+    LightingOnly,
+    /// every fragment adds a little brightness, so the brightest areas are drawn over the most
+    Overdraw
 }
 
+impl RenderMode {
+    /// The `render_mode` uniform, matching the `RENDER_MODE_` constants in render-modes.glsl
+    pub fn shader_value(&self) -> i32 {
+        *self as i32
+    }
 
-#[derive(Clone, Copy, Debug, Default)]
-pub struct RenderSettings {
-    pub debug_ui: bool,
-    pub wireframes: bool
+    /// Sets up the GL state the mode draws with, `end` puts it back
+    pub fn begin(&self) {
+        unsafe {
+            match *self {
+                RenderMode::Wireframe => gl::PolygonMode(gl::FRONT_AND_BACK, gl::LINE),
+                RenderMode::Overdraw => {
+                    gl::Enable(gl::BLEND);
+                    gl::BlendFunc(gl::ONE, gl::ONE);
+                    // hidden surfaces count too
+                    gl::Disable(gl::DEPTH_TEST);
+                },
+                _ => {}
+            }
+        }
+    }
+
+    pub fn end(&self) {
+        unsafe {
+            match *self {
+                RenderMode::Wireframe => gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL),
+                RenderMode::Overdraw => {
+                    gl::Disable(gl::BLEND);
+                    gl::Enable(gl::DEPTH_TEST);
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+
+/// Where the renderers draw to. A copyable handle so it can be passed around in resources,
+/// the `Fbo` it came from stays owned by whoever created it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RenderTarget {
+    #[default]
+    Screen,
+    Fbo { id: GLuint, width: u32, height: u32 }
+}
+
+impl RenderTarget {
+    pub fn bind(&self, window_size: &WindowSize) {
+        unsafe {
+            match *self {
+                RenderTarget::Screen => {
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                    gl::Viewport(0, 0, window_size.width as i32, window_size.height as i32);
+                },
+                RenderTarget::Fbo { id, width, height } => {
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, id);
+                    gl::Viewport(0, 0, width as i32, height as i32);
+                }
+            }
+        }
+    }
+}
+
+/// What kind of image a frame buffer attachment holds and in which internal format.
+/// Textures can be sampled afterwards, render buffers can only be drawn to and blitted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Attachment {
+    None,
+    Texture(GLenum),
+    RenderBuffer(GLenum)
+}
+
+/// An off-screen frame buffer with optional colour and depth attachments
+pub struct Fbo {
+    id: GLuint,
+    width: u32,
+    height: u32,
+    // more than one for a multisampled buffer, which has to be resolved before it can be sampled
+    samples: u32,
+    colour: Attachment,
+    depth: Attachment,
+    colour_texture: GLuint,
+    depth_texture: GLuint,
+    render_buffers: Vec<GLuint>
+}
+
+impl Fbo {
+
+    pub fn new(width: u32, height: u32, colour: Attachment, depth: Attachment) -> Result<Fbo, String> {
+        Fbo::with_samples(width, height, 1, colour, depth)
+    }
+
+    /// Colour and depth render buffers with `samples` samples per pixel
+    pub fn multisampled(width: u32, height: u32, samples: u32, colour_format: GLenum) -> Result<Fbo, String> {
+        Fbo::with_samples(width, height, samples, Attachment::RenderBuffer(colour_format), Attachment::RenderBuffer(gl::DEPTH_COMPONENT24))
+    }
+
+    fn with_samples(width: u32, height: u32, samples: u32, colour: Attachment, depth: Attachment) -> Result<Fbo, String> {
+        let mut id = 0;
+        unsafe {
+            gl::GenFramebuffers(1, &mut id);
+        }
+        let mut fbo = Fbo { id, width, height, samples, colour, depth, colour_texture: 0, depth_texture: 0, render_buffers: Vec::new() };
+        fbo.create_attachments()?;
+        Ok(fbo)
+    }
+
+    pub fn target(&self) -> RenderTarget {
+        RenderTarget::Fbo { id: self.id, width: self.width, height: self.height }
+    }
+
+    pub fn bind(&self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+            gl::Viewport(0, 0, self.width as i32, self.height as i32);
+        }
+    }
+
+    pub fn colour_texture(&self) -> GLuint {
+        self.colour_texture
+    }
+
+    pub fn depth_texture(&self) -> GLuint {
+        self.depth_texture
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Recreates the attachments at the new size, the old contents are lost
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        if width == self.width && height == self.height {
+            return Ok(());
+        }
+        self.delete_attachments();
+        self.width = width;
+        self.height = height;
+        self.create_attachments()
+    }
+
+    /// Copies the colour and depth into `target`, averaging the samples of a multisampled buffer
+    pub fn resolve(&self, target: &RenderTarget, window_size: &WindowSize) {
+        let (target_id, target_width, target_height) = match *target {
+            RenderTarget::Screen => (0, window_size.width, window_size.height),
+            RenderTarget::Fbo { id, width, height } => (id, width, height)
+        };
+        let mut mask = 0;
+        if self.colour != Attachment::None {
+            mask |= gl::COLOR_BUFFER_BIT;
+        }
+        if self.depth != Attachment::None {
+            mask |= gl::DEPTH_BUFFER_BIT;
+        }
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target_id);
+            // depth can only be copied pixel for pixel
+            let filter = if mask & gl::DEPTH_BUFFER_BIT != 0 { gl::NEAREST } else { gl::LINEAR };
+            gl::BlitFramebuffer(0, 0, self.width as i32, self.height as i32,
+                                0, 0, target_width as i32, target_height as i32,
+                                mask, filter);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    fn create_attachments(&mut self) -> Result<(), String> {
+        let status = unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+            match self.colour {
+                Attachment::None => {
+                    // depth only, e.g. a shadow map
+                    gl::DrawBuffer(gl::NONE);
+                    gl::ReadBuffer(gl::NONE);
+                },
+                Attachment::Texture(format) => self.colour_texture = self.attach_texture(gl::COLOR_ATTACHMENT0, format),
+                Attachment::RenderBuffer(format) => self.attach_render_buffer(gl::COLOR_ATTACHMENT0, format)
+            }
+            match self.depth {
+                Attachment::None => {},
+                Attachment::Texture(format) => self.depth_texture = self.attach_texture(gl::DEPTH_ATTACHMENT, format),
+                Attachment::RenderBuffer(format) => self.attach_render_buffer(gl::DEPTH_ATTACHMENT, format)
+            }
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            status
+        };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("Framebuffer {}x{} with {:?} colour and {:?} depth is not complete, status {:#x}",
+                               self.width, self.height, self.colour, self.depth, status));
+        }
+        Ok(())
+    }
+
+    unsafe fn attach_texture(&self, attachment: GLenum, internal_format: GLenum) -> GLuint {
+        let (format, data_type) = Fbo::pixel_format(internal_format);
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, self.width as i32, self.height as i32,
+                       0, format, data_type, ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, gl::TEXTURE_2D, texture, 0);
+        texture
+    }
+
+    unsafe fn attach_render_buffer(&mut self, attachment: GLenum, internal_format: GLenum) {
+        let mut render_buffer = 0;
+        gl::GenRenderbuffers(1, &mut render_buffer);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, render_buffer);
+        if self.samples > 1 {
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, self.samples as i32, internal_format, self.width as i32, self.height as i32);
+        } else {
+            gl::RenderbufferStorage(gl::RENDERBUFFER, internal_format, self.width as i32, self.height as i32);
+        }
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, render_buffer);
+        self.render_buffers.push(render_buffer);
+    }
+
+    // the format and type TexImage2D expects alongside an internal format, no data is uploaded
+    fn pixel_format(internal_format: GLenum) -> (GLenum, GLenum) {
+        match internal_format {
+            gl::DEPTH_COMPONENT16 | gl::DEPTH_COMPONENT24 | gl::DEPTH_COMPONENT32F => (gl::DEPTH_COMPONENT, gl::FLOAT),
+            gl::RGBA16F | gl::RGBA32F => (gl::RGBA, gl::FLOAT),
+            gl::RGB16F | gl::RGB32F | gl::R11F_G11F_B10F => (gl::RGB, gl::FLOAT),
+            gl::RGB | gl::RGB8 => (gl::RGB, gl::UNSIGNED_BYTE),
+            _ => (gl::RGBA, gl::UNSIGNED_BYTE)
+        }
+    }
+
+    fn delete_attachments(&mut self) {
+        unsafe {
+            if self.colour_texture != 0 {
+                gl::DeleteTextures(1, &self.colour_texture);
+            }
+            if self.depth_texture != 0 {
+                gl::DeleteTextures(1, &self.depth_texture);
+            }
+            for render_buffer in self.render_buffers.drain(..) {
+                gl::DeleteRenderbuffers(1, &render_buffer);
+            }
+        }
+        self.colour_texture = 0;
+        self.depth_texture = 0;
+    }
+}
+
+impl Drop for Fbo {
+    fn drop(&mut self) {
+        self.delete_attachments();
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
+
+/// Which view of the scene the terrain, entity and skybox renderers are drawing and where to.
+/// The water passes point them at an off-screen target before the frame itself is drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct ScenePass {
+    /// false when the pass has nothing to draw, e.g. no water in the world
+    pub enabled: bool,
+    pub target: RenderTarget,
+    /// world space plane, only what is on the side its normal points to is drawn while clipping is on
+    pub clip_plane: Vector4<f32>,
+    /// the water height the camera is mirrored at for a reflection, see `camera`
+    pub reflected_at: Option<f32>,
+    /// false for the water passes, so the debug stats only count what the frame itself draws
+    pub counts_stats: bool
+}
+
+impl ScenePass {
+    /// The camera the pass is drawn from, the shared one is never moved
+    pub fn camera(&self, camera: &Camera) -> Camera {
+        match self.reflected_at {
+            Some(water_height) => camera.mirrored_at(water_height),
+            None => camera.clone()
+        }
+    }
+}
+
+impl Default for ScenePass {
+    fn default() -> Self {
+        ScenePass {
+            enabled: true,
+            target: RenderTarget::Screen,
+            clip_plane: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            reflected_at: None,
+            counts_stats: true
+        }
+    }
+}
+
+/// Draws the scene into each water texture and then the frame itself. There is one renderer of
+/// each kind, run again for every pass, so their shaders and buffers are only made once.
+pub struct SceneRenderer {
+    reflection_pass: WaterPassRenderer,
+    refraction_pass: WaterPassRenderer,
+    screen_pass: WaterPassRenderer,
+    hdr_scene_pass: HdrScenePass,
+    frame_uniform_uploader: FrameUniformUploader,
+    terrain_renderer: TerrainRenderer,
+    entity_renderer: EntityRenderer,
+    skybox_renderer: SkyboxRenderer,
+    water_renderer: WaterRenderer
+}
+
+impl SceneRenderer {
+    pub fn new(resource_loader: &ResourceLoader, window_size: &WindowSize) -> Result<SceneRenderer, String> {
+        Ok(SceneRenderer {
+            reflection_pass: WaterPassRenderer::reflection(window_size)?,
+            refraction_pass: WaterPassRenderer::refraction(window_size)?,
+            screen_pass: WaterPassRenderer::screen(),
+            hdr_scene_pass: HdrScenePass::new(window_size)?,
+            frame_uniform_uploader: FrameUniformUploader::new(),
+            terrain_renderer: TerrainRenderer::new(resource_loader)?,
+            entity_renderer: EntityRenderer::new(resource_loader)?,
+            skybox_renderer: SkyboxRenderer::new(resource_loader)?,
+            water_renderer: WaterRenderer::new(resource_loader)?
+        })
+    }
+}
+
+impl<'a> RunNow<'a> for SceneRenderer {
+    fn run_now(&mut self, res: &'a Resources) {
+        // the scene above the water, seen from below the surface
+        self.reflection_pass.run_now(res);
+        self.frame_uniform_uploader.run_now(res);
+        self.terrain_renderer.run_now(res);
+        self.entity_renderer.run_now(res);
+        self.skybox_renderer.run_now(res);
+        // the ground under the water
+        self.refraction_pass.run_now(res);
+        self.frame_uniform_uploader.run_now(res);
+        self.terrain_renderer.run_now(res);
+        self.entity_renderer.run_now(res);
+        // the frame itself
+        self.screen_pass.run_now(res);
+        self.frame_uniform_uploader.run_now(res);
+        self.hdr_scene_pass.run_now(res);
+        ClearScreenRenderer.run_now(res);
+        self.terrain_renderer.run_now(res);
+        self.entity_renderer.run_now(res);
+        self.skybox_renderer.run_now(res);
+        self.water_renderer.run_now(res);
+        self.hdr_scene_pass.resolve(&res.fetch::<WindowSize>());
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        RunNow::setup(&mut self.reflection_pass, res);
+        RunNow::setup(&mut self.refraction_pass, res);
+        RunNow::setup(&mut self.screen_pass, res);
+        RunNow::setup(&mut self.hdr_scene_pass, res);
+        RunNow::setup(&mut self.frame_uniform_uploader, res);
+        RunNow::setup(&mut ClearScreenRenderer, res);
+        RunNow::setup(&mut self.terrain_renderer, res);
+        RunNow::setup(&mut self.entity_renderer, res);
+        RunNow::setup(&mut self.skybox_renderer, res);
+        RunNow::setup(&mut self.water_renderer, res);
+    }
 }
 
 
 pub struct EntityRenderer {
-    pub entity_shader: ShaderProgram
+    pub entity_shader: ShaderProgram,
+    pub instance_buffer: InstanceBuffer
+}
+
+/// A transformation matrix per instance, streamed in before each instanced draw and read by the
+/// shaders as a mat4 attribute at location 3
+pub struct InstanceBuffer {
+    vbo: GLuint
+}
+
+impl InstanceBuffer {
+    // a mat4 per instance, passed in as four vec4 attributes
+    const INSTANCE_DATA_LENGTH: usize = 16;
+    const ATTRIBUTE_START: GLuint = 3;
+
+    pub fn create() -> InstanceBuffer {
+        let mut vbo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vbo);
+        }
+        InstanceBuffer { vbo }
+    }
+
+    pub fn store(&self, instance_data: &[f32]) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            // orphan the previous contents so we don't stall on draws still using them
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           mem::size_of_val(instance_data) as GLsizeiptr,
+                           ptr::null(),
+                           gl::STREAM_DRAW);
+            gl::BufferSubData(gl::ARRAY_BUFFER,
+                              0,
+                              mem::size_of_val(instance_data) as GLsizeiptr,
+                              &instance_data[0] as *const f32 as *const c_void);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    /// Points the bound vertex array's instance attributes at the buffer
+    pub fn bind_attributes(&self) {
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            let stride = (InstanceBuffer::INSTANCE_DATA_LENGTH * mem::size_of::<GLfloat>()) as GLsizei;
+            for column in 0..4 {
+                let attribute_id = InstanceBuffer::ATTRIBUTE_START + column;
+                let offset = (column as usize * 4 * mem::size_of::<GLfloat>()) as *const c_void;
+                gl::VertexAttribPointer(attribute_id, 4, gl::FLOAT, gl::FALSE, stride, offset);
+                gl::VertexAttribDivisor(attribute_id, 1);
+                gl::EnableVertexAttribArray(attribute_id);
+            }
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    pub fn unbind_attributes() {
+        unsafe {
+            for column in 0..4 {
+                gl::DisableVertexAttribArray(InstanceBuffer::ATTRIBUTE_START + column);
+            }
+        }
+    }
+
+    /// With the attribute arrays disabled every vertex reads the same matrix, for drawing a model
+    /// that isn't instanced with the same shader
+    pub fn set_constant(matrix: &Matrix4<f32>) {
+        InstanceBuffer::unbind_attributes();
+        let columns: &[[f32; 4]; 4] = matrix.as_ref();
+        unsafe {
+            for (column, values) in columns.iter().enumerate() {
+                gl::VertexAttrib4fv(InstanceBuffer::ATTRIBUTE_START + column as GLuint, values.as_ptr());
+            }
+        }
+    }
+}
+
+impl Drop for InstanceBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+        }
+    }
+}
+
+/// Visible copies of one model part, drawn with a single instanced draw
+pub struct EntityBatch {
+    pub model: RawModel,
+    pub texture: SimpleTexture,
+    pub instance_data: Vec<f32>
+}
+
+// floats go in by their bits, entities sharing a material share the exact same values
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct BatchKey {
+    vao_id: GLuint,
+    texture_id: GLuint,
+    reflectivity: u32,
+    shine_damper: u32,
+    diffuse_colour: [u32; 3],
+    has_transparency: bool,
+    use_fake_lighting: bool
+}
+
+impl BatchKey {
+    fn new(model: &RawModel, texture: &SimpleTexture) -> BatchKey {
+        BatchKey {
+            vao_id: model.vao_id,
+            texture_id: texture.texture_id,
+            reflectivity: texture.reflectivity.to_bits(),
+            shine_damper: texture.shine_damper.to_bits(),
+            diffuse_colour: [texture.diffuse_colour.x.to_bits(), texture.diffuse_colour.y.to_bits(), texture.diffuse_colour.z.to_bits()],
+            has_transparency: texture.has_transparency,
+            use_fake_lighting: texture.use_fake_lighting
+        }
+    }
+}
+
+impl EntityBatch {
+    fn new(model: RawModel, texture: SimpleTexture) -> EntityBatch {
+        EntityBatch { model, texture, instance_data: Vec::new() }
+    }
+
+    fn add_instance(&mut self, transformation_matrix: &Matrix4<f32>) {
+        let matrix: &[f32; 16] = transformation_matrix.as_ref();
+        self.instance_data.extend_from_slice(matrix);
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instance_data.len() / InstanceBuffer::INSTANCE_DATA_LENGTH
+    }
+}
+
+/// The entities and multi-part model parts inside a frustum, grouped by whatever the pass
+/// drawing them has to switch state for
+pub struct EntityBatches<K> {
+    pub batches: HashMap<K, EntityBatch>,
+    pub drawn_count: i32,
+    pub culled_count: i32
+}
+
+impl<K: Eq + Hash> EntityBatches<K> {
+    pub fn collect<F>(frustum: &Frustum, transform: &ReadStorage<Transform>, model: &ReadStorage<RawModel>, simple_texture: &ReadStorage<SimpleTexture>,
+                      multi_part_model: &ReadStorage<MultiPartModel>, key: F) -> EntityBatches<K>
+        where F: Fn(&RawModel, &SimpleTexture) -> K {
+        let mut batches = EntityBatches { batches: HashMap::new(), drawn_count: 0, culled_count: 0 };
+        use super::specs::Join;
+        for (transform, model, texture) in (transform, model, simple_texture).join() {
+            let transformation_matrix = EntityRenderer::transformation_matrix(transform);
+            batches.add(frustum, transform, &transformation_matrix, model, texture, key(model, texture));
+        }
+        for (transform, multi_part_model) in (transform, multi_part_model).join() {
+            let transformation_matrix = EntityRenderer::transformation_matrix(transform);
+            for part in multi_part_model.parts.iter() {
+                batches.add(frustum, transform, &transformation_matrix, &part.raw_model, &part.texture, key(&part.raw_model, &part.texture));
+            }
+        }
+        batches
+    }
+
+    fn add(&mut self, frustum: &Frustum, transform: &Transform, transformation_matrix: &Matrix4<f32>, model: &RawModel, texture: &SimpleTexture, key: K) {
+        if !EntityBatches::<K>::is_visible(frustum, model, transform, transformation_matrix) {
+            self.culled_count += 1;
+            return;
+        }
+        self.drawn_count += 1;
+        self.batches.entry(key)
+            .or_insert_with(|| EntityBatch::new(model.clone(), texture.clone()))
+            .add_instance(transformation_matrix);
+    }
+
+    fn is_visible(frustum: &Frustum, model: &RawModel, transform: &Transform, transformation_matrix: &Matrix4<f32>) -> bool {
+        let center = (*transformation_matrix * model.bounding_box.center().extend(1.0)).truncate();
+        let max_scale = transform.scale.x.abs().max(transform.scale.y.abs()).max(transform.scale.z.abs());
+        frustum.contains_sphere(center, model.bounding_box.radius() * max_scale)
+    }
 }
 
 
@@ -42,29 +602,52 @@ impl<'a> System<'a> for EntityRenderer {
     type SystemData = (ReadStorage<'a, Transform>,
                        ReadStorage<'a, SimpleTexture>,
                        ReadStorage<'a, RawModel>,
+                       ReadStorage<'a, MultiPartModel>,
                        Read<'a, Camera>,
-                       Read<'a, Fog>,
-                       Read<'a, Light>,
+                       Read<'a, ShadowMap>,
+                       Read<'a, ScenePass>,
+                       Read<'a, RenderSettings>,
                        Read<'a, WindowSize>,
                        Write<'a, DebugInfo>);
 
-    fn run(&mut self, (transform, simple_texture, model, camera, fog, light, window_size, mut debug_info): Self::SystemData) {
-        self.prepare(&window_size);
+    fn run(&mut self, (transform, simple_texture, model, multi_part_model, camera, shadow_map, scene_pass, render_settings, window_size, mut debug_info): Self::SystemData) {
+        if !scene_pass.enabled {
+            return;
+        }
+        scene_pass.target.bind(&window_size);
+        self.prepare();
         self.entity_shader.start();
-        self.bind_environment(&camera, &light, &fog);
-
-        use super::specs::Join;
-        for (transform, model, texture) in (&transform, &model, &simple_texture).join() {
-            self.bind_model(&model, &texture);
-            self.bind_entity(&transform);
-            debug_info.current_frame_draw_calls = debug_info.current_frame_draw_calls + 1;
-            debug_info.current_frame_triangle_count = debug_info.current_frame_triangle_count + (model.vertex_count/3) as i32;
+        self.bind_shadow_map(&shadow_map);
+        unsafe {
+            self.entity_shader.set_vector4(c_str!("clip_plane"), &scene_pass.clip_plane);
+            self.entity_shader.set_int(c_str!("render_mode"), render_settings.render_mode.shader_value());
+        }
+        render_settings.render_mode.begin();
+        let camera = scene_pass.camera(&camera);
+        let frustum = camera.get_frustum(&camera.projection_matrix(&window_size));
+
+        // group visible entities sharing a vao and material so each group is a single instanced draw
+        let batches = EntityBatches::collect(&frustum, &transform, &model, &simple_texture, &multi_part_model, BatchKey::new);
+        let mut stats = DrawStats { drawn_count: batches.drawn_count, culled_count: batches.culled_count, ..DrawStats::default() };
+
+        for batch in batches.batches.values() {
+            let instance_count = batch.instance_count();
+            self.bind_model(&batch.model, &batch.texture);
+            self.instance_buffer.store(&batch.instance_data);
+            stats.draw_calls += 1;
+            stats.triangle_count += (batch.model.vertex_count/3 * instance_count) as i32;
             unsafe {
-                gl::DrawElements(gl::TRIANGLES, model.vertex_count as i32, gl::UNSIGNED_INT, ptr::null());
+                gl::DrawElementsInstanced(gl::TRIANGLES, batch.model.vertex_count as i32, gl::UNSIGNED_INT, ptr::null(), instance_count as i32);
             }
             self.unbind_model();
         }
+        // leave culling as the other renderers expect it
+        EntityRenderer::disable_culling();
+        render_settings.render_mode.end();
         self.entity_shader.stop();
+        if scene_pass.counts_stats {
+            debug_info.add(&stats);
+        }
     }
 }
 
@@ -72,43 +655,37 @@ impl EntityRenderer {
 
     const VERTEX_SHADER: &'static str = "shaders/default.vert";
     const FRAGMENT_SHADER: &'static str = "shaders/default.frag";
+    // the model texture uses unit 0
+    const SHADOW_MAP_UNIT: GLuint = 1;
 
-    pub fn new(resource_loader: &ResourceLoader) -> EntityRenderer {
-        let entity_shader = ShaderProgram::from_shader_files(&resource_loader, EntityRenderer::VERTEX_SHADER, EntityRenderer::FRAGMENT_SHADER).unwrap();
-        EntityRenderer {entity_shader}
+    pub fn new(resource_loader: &ResourceLoader) -> Result<EntityRenderer, String> {
+        let entity_shader = FrameUniformUploader::shader_program(resource_loader, EntityRenderer::VERTEX_SHADER, EntityRenderer::FRAGMENT_SHADER)
+            .map_err(|e| e.to_string())?;
+        Ok(EntityRenderer {entity_shader, instance_buffer: InstanceBuffer::create()})
     }
 
-    pub fn prepare(&self, window_size: &WindowSize) {
+    /// The camera, lights and fog come from the `FrameUniforms` block
+    pub fn prepare(&self) {
         self.entity_shader.start();
         unsafe {
-            let projection_matrix = perspective(Deg(RenderConstants::FOV), window_size.width as f32 / window_size.height as f32, RenderConstants::NEAR_PLANE, RenderConstants::FAR_PLANE);
-            self.entity_shader.set_mat4(c_str!("projection_matrix"), &projection_matrix);
+            self.entity_shader.set_int(c_str!("shadow_map_sampler"), EntityRenderer::SHADOW_MAP_UNIT as i32);
         }
         self.entity_shader.stop();
     }
 
-    fn bind_environment(&self, camera: &Camera, light: &Light, fog: &Fog) {
+    fn bind_shadow_map(&self, shadow_map: &ShadowMap) {
         unsafe {
-            self.entity_shader.set_mat4(c_str!("view_matrix"), &camera.get_view_matrix());
-            self.entity_shader.set_vector3(c_str!("light_position"), &light.position);
-            self.entity_shader.set_vector3(c_str!("light_colour"), &light.colour);
-            self.entity_shader.set_vector3(c_str!("day_sky_colour"), &fog.day_colour);
-            self.entity_shader.set_vector3(c_str!("night_sky_colour"), &fog.night_colour);
-            self.entity_shader.set_float(c_str!("blend_factor"), 0.5);
-            self.entity_shader.set_float(c_str!("fog_density"), fog.density);
-            self.entity_shader.set_float(c_str!("fog_gradient"), fog.gradient);
+            gl::ActiveTexture(gl::TEXTURE0 + EntityRenderer::SHADOW_MAP_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, shadow_map.texture_id);
         }
     }
 
-    fn bind_entity(&self, transform: &Transform) {
-        unsafe {
-            let t = Matrix4::from_translation(transform.position)
-                * Matrix4::from_nonuniform_scale(transform.scale.x, transform.scale.y, transform.scale.z)
-                * Matrix4::from_angle_x(Deg(transform.rotation.x))
-                * Matrix4::from_angle_y(Deg(transform.rotation.y))
-                * Matrix4::from_angle_z(Deg(transform.rotation.z));
-            self.entity_shader.set_mat4(c_str!("transformation_matrix"), &t);
-        }
+    pub fn transformation_matrix(transform: &Transform) -> Matrix4<f32> {
+        Matrix4::from_translation(transform.position)
+            * Matrix4::from_nonuniform_scale(transform.scale.x, transform.scale.y, transform.scale.z)
+            * Matrix4::from_angle_x(Deg(transform.rotation.x))
+            * Matrix4::from_angle_y(Deg(transform.rotation.y))
+            * Matrix4::from_angle_z(Deg(transform.rotation.z))
     }
 
     fn bind_model(&self, model: &RawModel, texture: &SimpleTexture) {
@@ -117,12 +694,33 @@ impl EntityRenderer {
             gl::EnableVertexAttribArray(0); // positions
             gl::EnableVertexAttribArray(1); // texture coords
             gl::EnableVertexAttribArray(2); // normals
+            self.instance_buffer.bind_attributes(); // transformation matrices
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, texture.texture_id);
 
             // set uniforms
             self.entity_shader.set_float(c_str!("reflectivity"), texture.reflectivity);
             self.entity_shader.set_float(c_str!("shine_damper"), texture.shine_damper);
+            self.entity_shader.set_vector3(c_str!("diffuse_colour"), &texture.diffuse_colour);
+            self.entity_shader.set_float(c_str!("use_fake_lighting"), if texture.use_fake_lighting { 1.0 } else { 0.0 });
+        }
+        if texture.has_transparency {
+            EntityRenderer::disable_culling();
+        } else {
+            EntityRenderer::enable_culling();
+        }
+    }
+
+    fn enable_culling() {
+        unsafe {
+            gl::Enable(gl::CULL_FACE);
+            gl::CullFace(gl::BACK);
+        }
+    }
+
+    fn disable_culling() {
+        unsafe {
+            gl::Disable(gl::CULL_FACE);
         }
     }
 
@@ -131,6 +729,7 @@ impl EntityRenderer {
             gl::DisableVertexAttribArray(0);
             gl::DisableVertexAttribArray(1);
             gl::DisableVertexAttribArray(2);
+            InstanceBuffer::unbind_attributes();
             gl::BindVertexArray(0);
         }
     }
@@ -140,11 +739,20 @@ impl EntityRenderer {
 pub struct ClearScreenRenderer;
 
 impl<'a> System<'a> for ClearScreenRenderer {
-    type SystemData = ();
-    fn run(&mut self, (): Self::SystemData) {
+    type SystemData = (Read<'a, ScenePass>,
+                       Read<'a, RenderSettings>,
+                       Read<'a, WindowSize>);
+
+    fn run(&mut self, (scene_pass, render_settings, window_size): Self::SystemData) {
+        scene_pass.target.bind(&window_size);
         unsafe {
             gl::Enable(gl::DEPTH_TEST);
-            gl::ClearColor(1.0, 1.0, 1.0, 1.0);
+            if render_settings.render_mode == RenderMode::Overdraw {
+                // overdraw adds up from nothing
+                gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+            } else {
+                gl::ClearColor(1.0, 1.0, 1.0, 1.0);
+            }
             gl::Clear(gl::COLOR_BUFFER_BIT|gl::DEPTH_BUFFER_BIT);
         }
     }
@@ -152,36 +760,160 @@ impl<'a> System<'a> for ClearScreenRenderer {
 
 pub struct TerrainRenderer {
     pub terrain_shader: ShaderProgram,
+    patches: TerrainPatches
+}
+
+/// Draws tiles patch by patch at the level of detail for their distance from the camera
+#[derive(Default)]
+pub struct TerrainPatches {
+    // tiles with the same vertex count share their patch indices
+    lod_index_buffers: HashMap<u32, LodIndexBuffer>
+}
+
+/// Every patch index list of `TerrainLod` for one tile resolution, one after the other in one buffer
+struct LodIndexBuffer {
+    ebo: GLuint,
+    // offset in bytes and index count, by level then stitch mask
+    ranges: Vec<(usize, i32)>
+}
+
+impl LodIndexBuffer {
+    fn new(vertex_count: u32) -> LodIndexBuffer {
+        let mut indices = Vec::new();
+        let mut ranges = Vec::with_capacity((TerrainLod::LEVELS * TerrainLod::STITCH_COMBINATIONS) as usize);
+        for level in 0..TerrainLod::LEVELS {
+            for stitch_mask in 0..TerrainLod::STITCH_COMBINATIONS {
+                let patch_indices = TerrainLod::patch_indices(vertex_count, level, stitch_mask);
+                ranges.push((indices.len() * mem::size_of::<u32>(), patch_indices.len() as i32));
+                indices.extend(patch_indices);
+            }
+        }
+        let mut ebo = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut ebo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
+                           (indices.len() * mem::size_of::<u32>()) as GLsizeiptr,
+                           indices.as_ptr() as *const c_void,
+                           gl::STATIC_DRAW);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+        LodIndexBuffer { ebo, ranges }
+    }
+
+    fn range(&self, level: u32, stitch_mask: u32) -> (usize, i32) {
+        self.ranges[(level * TerrainLod::STITCH_COMBINATIONS + stitch_mask) as usize]
+    }
+}
+
+impl Drop for LodIndexBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.ebo);
+        }
+    }
+}
+
+impl TerrainPatches {
+    /// Draws the patches of a tile inside `frustum`, with the tile's vertex array bound
+    pub fn draw(&mut self, model: &RawModel, terrain_physics: &TerrainPhysics, camera: &Camera, frustum: &Frustum, stats: &mut DrawStats) {
+        let vertex_count = terrain_physics.heights.vertex_count();
+        let lod_index_buffer = self.lod_index_buffers.entry(vertex_count)
+            .or_insert_with(|| LodIndexBuffer::new(vertex_count));
+        let patch_count = (vertex_count - 1) / TerrainLod::PATCH_QUADS;
+        let patch_size = terrain_physics.size / patch_count as f32;
+        let level_for_patch = |px: i32, pz: i32| TerrainLod::level_for_patch(camera.position.x, camera.position.z,
+                                                                             terrain_physics.x + px as f32 * patch_size,
+                                                                             terrain_physics.z + pz as f32 * patch_size,
+                                                                             patch_size);
+        unsafe {
+            // the tile's vertex array has no indices of its own
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, lod_index_buffer.ebo);
+        }
+        for pz in 0..patch_count as i32 {
+            for px in 0..patch_count as i32 {
+                let patch_x = terrain_physics.x + px as f32 * patch_size;
+                let patch_z = terrain_physics.z + pz as f32 * patch_size;
+                let patch_box = BoundingBox {
+                    min: Vector3 { x: patch_x, y: model.bounding_box.min.y, z: patch_z },
+                    max: Vector3 { x: patch_x + patch_size, y: model.bounding_box.max.y, z: patch_z + patch_size }
+                };
+                if !frustum.contains_box(&patch_box) {
+                    continue;
+                }
+
+                // neighbours in other tiles are worked out the same way so seams between tiles match too
+                let level = level_for_patch(px, pz);
+                let mut stitch_mask = 0;
+                if level_for_patch(px, pz - 1) > level { stitch_mask |= TerrainLod::NORTH; }
+                if level_for_patch(px + 1, pz) > level { stitch_mask |= TerrainLod::EAST; }
+                if level_for_patch(px, pz + 1) > level { stitch_mask |= TerrainLod::SOUTH; }
+                if level_for_patch(px - 1, pz) > level { stitch_mask |= TerrainLod::WEST; }
+
+                let (offset, count) = lod_index_buffer.range(level, stitch_mask);
+                let base_vertex = pz as u32 * TerrainLod::PATCH_QUADS * vertex_count + px as u32 * TerrainLod::PATCH_QUADS;
+                stats.draw_calls += 1;
+                stats.triangle_count += count / 3;
+                unsafe {
+                    gl::DrawElementsBaseVertex(gl::TRIANGLES, count, gl::UNSIGNED_INT, offset as *const c_void, base_vertex as i32);
+                }
+            }
+        }
+        unsafe {
+            // taken out of the vertex array again, which would otherwise hold on to it
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+        }
+    }
 }
 
 impl<'a> System<'a> for TerrainRenderer {
 
     type SystemData = (ReadStorage<'a, TerrainTexturePack>,
                        ReadStorage<'a, RawModel>,
-                       ReadStorage<'a, GridPosition>,
+                       ReadStorage<'a, TerrainPhysics>,
                        Read<'a, Camera>,
-                       Read<'a, Fog>,
-                       Read<'a, Light>,
+                       Read<'a, ShadowMap>,
+                       Read<'a, ScenePass>,
+                       Read<'a, RenderSettings>,
                        Read<'a, WindowSize>,
                        Write<'a, DebugInfo>);
 
-    fn run(&mut self, (terrain_texture_pack, model, grid_position, camera, fog, light, window_size, mut debug_info): Self::SystemData) {
-        self.prepare(&window_size);
+    fn run(&mut self, (terrain_texture_pack, model, terrain_physics, camera, shadow_map, scene_pass, render_settings, window_size, mut debug_info): Self::SystemData) {
+        if !scene_pass.enabled {
+            return;
+        }
+        scene_pass.target.bind(&window_size);
+        self.prepare();
         self.terrain_shader.start();
-        self.bind_environment(&camera, &light, &fog);
+        self.bind_shadow_map(&shadow_map);
+        unsafe {
+            self.terrain_shader.set_vector4(c_str!("clip_plane"), &scene_pass.clip_plane);
+            self.terrain_shader.set_int(c_str!("render_mode"), render_settings.render_mode.shader_value());
+        }
+        render_settings.render_mode.begin();
+        let camera = scene_pass.camera(&camera);
+        let frustum = camera.get_frustum(&camera.projection_matrix(&window_size));
 
+        let mut stats = DrawStats::default();
         use super::specs::Join;
-        for (model, terrain_texture_pack, grid_position) in (&model, &terrain_texture_pack, &grid_position).join() {
-            self.bind_model(&model);
-            self.bind_terrain(&terrain_texture_pack, &grid_position);
-            debug_info.current_frame_draw_calls = debug_info.current_frame_draw_calls + 1;
-            debug_info.current_frame_triangle_count = debug_info.current_frame_triangle_count + (model.vertex_count/3) as i32;
-            unsafe {
-                gl::DrawElements(gl::TRIANGLES, model.vertex_count as i32, gl::UNSIGNED_INT, ptr::null());
+        for (model, terrain_texture_pack, terrain_physics) in (&model, &terrain_texture_pack, &terrain_physics).join() {
+            let translation = TerrainRenderer::terrain_translation(terrain_physics);
+            if !frustum.contains_box(&model.bounding_box.translate(translation)) {
+                stats.culled_count += 1;
+                continue;
             }
+            stats.drawn_count += 1;
+            self.bind_model(model);
+            self.bind_terrain(terrain_texture_pack, terrain_physics);
+            // the config is checked to split into patches before any tile is made
+            self.patches.draw(model, terrain_physics, &camera, &frustum, &mut stats);
             self.unbind_model();
         }
+        render_settings.render_mode.end();
         self.terrain_shader.stop();
+        if scene_pass.counts_stats {
+            debug_info.add(&stats);
+        }
     }
 }
 
@@ -189,22 +921,19 @@ impl TerrainRenderer {
 
     const VERTEX_SHADER: &'static str = "shaders/terrain.vert";
     const FRAGMENT_SHADER: &'static str = "shaders/terrain.frag";
+    // after the five textures of the texture pack
+    const SHADOW_MAP_UNIT: GLuint = 5;
 
-    pub fn new(resource_loader: &ResourceLoader) -> TerrainRenderer {
-        let terrain_shader = ShaderProgram::from_shader_files(&resource_loader, TerrainRenderer::VERTEX_SHADER, TerrainRenderer::FRAGMENT_SHADER).unwrap();
-        TerrainRenderer {terrain_shader}
+    pub fn new(resource_loader: &ResourceLoader) -> Result<TerrainRenderer, String> {
+        let terrain_shader = FrameUniformUploader::shader_program(resource_loader, TerrainRenderer::VERTEX_SHADER, TerrainRenderer::FRAGMENT_SHADER)
+            .map_err(|e| e.to_string())?;
+        Ok(TerrainRenderer {terrain_shader, patches: TerrainPatches::default()})
     }
 
-    fn bind_environment(&self, camera: &Camera, light: &Light, fog: &Fog) {
+    fn bind_shadow_map(&self, shadow_map: &ShadowMap) {
         unsafe {
-            self.terrain_shader.set_mat4(c_str!("view_matrix"), &camera.get_view_matrix());
-            self.terrain_shader.set_vector3(c_str!("light_position"), &light.position);
-            self.terrain_shader.set_vector3(c_str!("light_colour"), &light.colour);
-            self.terrain_shader.set_vector3(c_str!("day_sky_colour"), &fog.day_colour);
-            self.terrain_shader.set_vector3(c_str!("night_sky_colour"), &fog.night_colour);
-            self.terrain_shader.set_float(c_str!("blend_factor"), 0.5);
-            self.terrain_shader.set_float(c_str!("fog_density"), fog.density);
-            self.terrain_shader.set_float(c_str!("fog_gradient"), fog.gradient);
+            gl::ActiveTexture(gl::TEXTURE0 + TerrainRenderer::SHADOW_MAP_UNIT);
+            gl::BindTexture(gl::TEXTURE_2D, shadow_map.texture_id);
         }
     }
 
@@ -230,10 +959,15 @@ impl TerrainRenderer {
         }
     }
 
-    fn bind_terrain(&self, terrain_texture_pack: &TerrainTexturePack, grid_position: &GridPosition) {
+    // the world space origin of the tile, meshes are built from 0 to the tile size
+    fn terrain_translation(terrain_physics: &TerrainPhysics) -> Vector3<f32> {
+        Vector3 {x: terrain_physics.x, y: 0.0, z: terrain_physics.z}
+    }
+
+    fn bind_terrain(&self, terrain_texture_pack: &TerrainTexturePack, terrain_physics: &TerrainPhysics) {
         unsafe {
-            self.bind_textures(&terrain_texture_pack);
-            let transformation = Matrix4::from_translation(Vector3 {x: grid_position.x as f32, y: 0.0, z: grid_position.z as f32});
+            self.bind_textures(terrain_texture_pack);
+            let transformation = Matrix4::from_translation(TerrainRenderer::terrain_translation(terrain_physics));
             self.terrain_shader.set_mat4(c_str!("transformation_matrix"), &transformation);
         }
     }
@@ -253,17 +987,16 @@ impl TerrainRenderer {
         }
     }
 
-    pub fn prepare(&self, window_size: &WindowSize) {
+    /// The camera, lights and fog come from the `FrameUniforms` block
+    pub fn prepare(&self) {
         self.terrain_shader.start();
         unsafe {
-            //TODO we only need to run this once on load
-            let projection_matrix = perspective(Deg(RenderConstants::FOV), window_size.width as f32 / window_size.height as f32, RenderConstants::NEAR_PLANE, RenderConstants::FAR_PLANE);
-            self.terrain_shader.set_mat4(c_str!("projection_matrix"), &projection_matrix);
             self.terrain_shader.set_int(c_str!("background_sampler"), 0);
             self.terrain_shader.set_int(c_str!("r_sampler"), 1);
             self.terrain_shader.set_int(c_str!("g_sampler"), 2);
             self.terrain_shader.set_int(c_str!("b_sampler"), 3);
             self.terrain_shader.set_int(c_str!("blend_map_sampler"), 4);
+            self.terrain_shader.set_int(c_str!("shadow_map_sampler"), TerrainRenderer::SHADOW_MAP_UNIT as i32);
         }
         self.terrain_shader.stop();
     }
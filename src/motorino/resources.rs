@@ -3,11 +3,13 @@ use self::image::{DynamicImage};
 
 use std::path::{Path, PathBuf};
 use std::fs;
-use std::ffi::CString;
+use std::time::SystemTime;
 
 
 pub struct ResourceLoader {
-    res_root_path: PathBuf
+    res_root_path: PathBuf,
+    // the res tree in the checkout, which build.rs copies next to the executable
+    source_root_path: Option<PathBuf>
 }
 
 impl ResourceLoader {
@@ -19,27 +21,58 @@ impl ResourceLoader {
             .ok_or("Unable to get exe path")?;
 
         Ok(ResourceLoader {
-            res_root_path: exe_path.join(path)
+            res_root_path: exe_path.join(path),
+            source_root_path: ResourceLoader::find_source_root()
         })
     }
 
+    // only debug builds look for the checkout, a release build may be run anywhere
+    fn find_source_root() -> Option<PathBuf> {
+        if !cfg!(debug_assertions) {
+            return None;
+        }
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("res");
+        if path.is_dir() { Some(path) } else { None }
+    }
+
+    /// Whether resources can be watched for changes in the checkout
+    pub fn can_watch(&self) -> bool {
+        self.source_root_path.is_some()
+    }
+
+    /// When the resource was last changed in the checkout
+    pub fn source_modified(&self, resource_name: &str) -> Option<SystemTime> {
+        let source_root_path = self.source_root_path.as_ref()?;
+        fs::metadata(source_root_path.join(resource_name))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Reads the resource from the checkout rather than the copy, so edits show without a rebuild
+    pub fn load_source_string(&self, resource_name: &str) -> Result<String, String> {
+        let source_root_path = self.source_root_path.as_ref()
+            .ok_or("No res directory to load from")?;
+        let filename = source_root_path.join(resource_name);
+        let contents = fs::read_to_string(&filename).
+            map_err(|_| format!("Unable to read file {}", filename.display()))?;
+        Ok(contents)
+    }
+
     pub fn to_real_path(&self, path: &Path) -> PathBuf {
-        return self.res_root_path.join(path);
+        self.res_root_path.join(path)
     }
 
-     pub fn load_cstring(&self, resource_name: &str) -> Result<CString, String> {
+     pub fn load_string(&self, resource_name: &str) -> Result<String, String> {
         let filename = self.res_root_path.join(resource_name);
         let contents = fs::read_to_string(&filename).
             map_err(|_| format!("Unable to read file {}", filename.display()))?;
-        let ret_val = CString::new(contents.as_bytes()).
-            map_err(|_| "Unable to convert file contents")?;
-        return Ok(ret_val);
+        Ok(contents)
     }
 
     pub fn load_image(&self, resource_name: &str) -> Result<DynamicImage, String> {
         let filename = self.res_root_path.join(resource_name);
         let img = image::open(&filename).
             map_err(|_| format!("Unable to read file {}", filename.display()))?;
-        return Ok(img);
+        Ok(img)
     }
 }
\ No newline at end of file
@@ -0,0 +1,93 @@
+use super::cgmath::Vector3;
+use super::components::{GridPosition, PointLight, RawModel, SimpleTexture, Transform, Water};
+use super::rand::prelude::*;
+use super::specs::{Builder, LazyUpdate};
+use super::specs::world::EntitiesRes;
+use super::terrain::{Heights, Terrain};
+
+/// Copies of a model scattered at random over each chunk
+#[derive(Clone)]
+pub struct SceneryLayer {
+    pub model: RawModel,
+    pub texture: SimpleTexture,
+    pub count_per_chunk: usize,
+    pub scale: f32
+}
+
+/// A lamp at the same place on every chunk, relative to the chunk's corner
+#[derive(Clone, Copy, Debug)]
+pub struct SceneryLamp {
+    pub offset: Vector3<f32>,
+    pub colour: Vector3<f32>
+}
+
+/// Everything placed on a chunk as it streams in. Each entity is given the chunk's
+/// `GridPosition` so the streamer can delete it along with the chunk.
+#[derive(Clone, Default)]
+pub struct Scenery {
+    pub layers: Vec<SceneryLayer>,
+    pub lamps: Vec<SceneryLamp>,
+    /// floods the lowest hollow of each chunk
+    pub lakes: bool
+}
+
+impl Scenery {
+    const LAKE_SIZE: f32 = 160.0;
+    const LAKE_DEPTH: f32 = 4.0;
+    const LAKE_SEARCH_STEP: f32 = 10.0;
+
+    /// The entities are created when the world is next maintained
+    pub fn place(&self, grid_position: GridPosition, terrain: &Terrain, entities: &EntitiesRes, lazy: &LazyUpdate) {
+        let height_at = |x: f32, z: f32| Terrain::get_height_of_terrain(&terrain.heights, terrain.config.size, x, z, terrain.x, terrain.z);
+
+        let mut rng = thread_rng();
+        for layer in self.layers.iter() {
+            for _i in 0..layer.count_per_chunk {
+                let x = terrain.x + rng.gen::<f32>() * terrain.config.size;
+                let z = terrain.z + rng.gen::<f32>() * terrain.config.size;
+                lazy.create_entity(entities)
+                    .with(grid_position)
+                    .with(Transform { position: Vector3 { x, y: height_at(x, z), z }, scale: Vector3 { x: layer.scale, y: layer.scale, z: layer.scale }, ..Transform::default() })
+                    .with(layer.model.clone())
+                    .with(layer.texture.clone())
+                    .build();
+            }
+        }
+
+        for lamp in self.lamps.iter() {
+            let x = terrain.x + lamp.offset.x;
+            let z = terrain.z + lamp.offset.z;
+            lazy.create_entity(entities)
+                .with(grid_position)
+                .with(Transform { position: Vector3 { x, y: height_at(x, z), z }, ..Transform::default() })
+                .with(PointLight { colour: lamp.colour, offset: Vector3 { x: 0.0, y: 10.0, z: 0.0 }, ..PointLight::default() })
+                .build();
+        }
+
+        if self.lakes {
+            let lowest = Scenery::lowest_point(&terrain.heights, terrain.config.size, terrain.x, terrain.z);
+            lazy.create_entity(entities)
+                .with(grid_position)
+                .with(Transform { position: Vector3 { y: lowest.y + Scenery::LAKE_DEPTH, ..lowest }, ..Transform::default() })
+                .with(Water { size: Scenery::LAKE_SIZE })
+                .build();
+        }
+    }
+
+    // searched on a coarse grid, a lake only needs to sit roughly in a hollow
+    fn lowest_point(heights: &Heights, size: f32, origin_x: f32, origin_z: f32) -> Vector3<f32> {
+        let steps = (size / Scenery::LAKE_SEARCH_STEP) as i32;
+        let mut lowest = Vector3 { x: origin_x, y: f32::MAX, z: origin_z };
+        for i in 0..steps {
+            for j in 0..steps {
+                let x = origin_x + i as f32 * Scenery::LAKE_SEARCH_STEP;
+                let z = origin_z + j as f32 * Scenery::LAKE_SEARCH_STEP;
+                let height = Terrain::get_height_of_terrain(heights, size, x, z, origin_x, origin_z);
+                if height < lowest.y {
+                    lowest = Vector3 { x, y: height, z };
+                }
+            }
+        }
+        lowest
+    }
+}
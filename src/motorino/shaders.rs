@@ -1,10 +1,136 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use std::ptr;
 use std::ffi::{CStr, CString};
-use super::cgmath::{Matrix, Matrix4, Vector3};
+use std::rc::{Rc, Weak};
+use std::time::SystemTime;
+use super::cgmath::{Matrix, Matrix4, Vector3, Vector4};
 use super::cgmath::prelude::*;
 use super::gl;
 use super::gl::types::*;
+use super::preprocessor::preprocess;
 use super::resources::ResourceLoader;
+use super::specs::{Read, System, Write};
+use super::UpdateDeltaTime;
+
+// =================================================================================================
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment
+}
+
+impl ShaderStage {
+    fn gl_kind(&self) -> GLenum {
+        match *self {
+            ShaderStage::Vertex => gl::VERTEX_SHADER,
+            ShaderStage::Fragment => gl::FRAGMENT_SHADER
+        }
+    }
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShaderStage::Vertex => write!(f, "vertex"),
+            ShaderStage::Fragment => write!(f, "fragment")
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ShaderError {
+    /// Unable to read a source file
+    Load(String),
+    /// The log has its line numbers pointing into the source files
+    Compile { file: String, stage: ShaderStage, log: String },
+    Link { log: String },
+    /// Not in the program, or unused and optimised away
+    MissingUniform { name: String },
+    /// Set with a value that doesn't match its type in the program
+    UniformType { name: String, glsl_type: String, value_type: &'static str }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShaderError::Load(ref message) => write!(f, "{}", message),
+            ShaderError::Compile { ref file, stage, ref log } => write!(f, "Failed to compile {} shader {}:\n{}", stage, file, log),
+            ShaderError::Link { ref log } => write!(f, "Failed to link shader program:\n{}", log),
+            ShaderError::MissingUniform { ref name } => write!(f, "No active uniform {}", name),
+            ShaderError::UniformType { ref name, ref glsl_type, value_type } => write!(f, "Uniform {} is a {}, not a {}", name, glsl_type, value_type)
+        }
+    }
+}
+
+impl Error for ShaderError {
+}
+
+impl From<String> for ShaderError {
+    fn from(message: String) -> ShaderError {
+        ShaderError::Load(message)
+    }
+}
+
+/// The source file and line each line of a shader's source came from, so the line numbers in
+/// the compile log can be put back the way they were written
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    lines: Vec<(String, u32)>
+}
+
+impl SourceMap {
+    /// The next line of the source came from `line` of `file`
+    pub fn push(&mut self, file: &str, line: u32) {
+        self.lines.push((file.to_string(), line));
+    }
+
+    /// The file the source was loaded from, rather than any it includes
+    pub fn file(&self) -> &str {
+        self.lines.first().map(|&(ref file, _line)| file.as_str()).unwrap_or("")
+    }
+
+    /// Rewrites the `0:12(3)`, `0(12)` and `ERROR: 0:12:` line locations drivers use as `file:line`
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|log_line| match SourceMap::find_location(log_line) {
+                Some((start, end, line)) if line >= 1 && line as usize <= self.lines.len() => {
+                    let (ref file, source_line) = self.lines[line as usize - 1];
+                    format!("{}{}:{}{}", &log_line[..start], file, source_line, &log_line[end..])
+                },
+                _ => log_line.to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // the byte range of the first `<source>:<line>` or `<source>(<line>)` and the line number in it
+    fn find_location(log_line: &str) -> Option<(usize, usize, u32)> {
+        let bytes = log_line.as_bytes();
+        let digits_end = |from: usize| (from..bytes.len()).find(|&i| !bytes[i].is_ascii_digit()).unwrap_or(bytes.len());
+        let mut start = 0;
+        while start < bytes.len() {
+            if !bytes[start].is_ascii_digit() || (start > 0 && bytes[start - 1].is_ascii_alphanumeric()) {
+                start += 1;
+                continue;
+            }
+            let separator = digits_end(start);
+            if separator < bytes.len() && (bytes[separator] == b':' || bytes[separator] == b'(') {
+                let line_end = digits_end(separator + 1);
+                if line_end > separator + 1 {
+                    let line = log_line[separator + 1..line_end].parse().ok()?;
+                    let closed = bytes[separator] == b'(' && line_end < bytes.len() && bytes[line_end] == b')';
+                    return Some((start, if closed { line_end + 1 } else { line_end }, line));
+                }
+            }
+            start = separator;
+        }
+        None
+    }
+}
+// =================================================================================================
 
 // =================================================================================================
 pub struct Shader {
@@ -14,48 +140,49 @@ pub struct Shader {
 impl Shader {
     pub fn from_source(
         source: &CStr,
-        kind: GLenum
-    ) -> Result<Shader, String> {
-        let id = Shader::shader_from_source(source, kind)?;
+        stage: ShaderStage,
+        source_map: &SourceMap
+    ) -> Result<Shader, ShaderError> {
+        let id = Shader::shader_from_source(source, stage, source_map)?;
         Ok(Shader { id })
     }
 
-    fn shader_from_source(source: &CStr, kind: GLenum) -> Result<GLuint, String> {
-        let id = unsafe { gl::CreateShader(kind) };
+    fn shader_from_source(source: &CStr, stage: ShaderStage, source_map: &SourceMap) -> Result<GLuint, ShaderError> {
+        let id = unsafe { gl::CreateShader(stage.gl_kind()) };
         unsafe {
             gl::ShaderSource(id, 1, &source.as_ptr(), ptr::null());
             gl::CompileShader(id);
-            Shader::check_compile_error(id, kind);
+            if let Err(log) = Shader::check_compile_error(id) {
+                gl::DeleteShader(id);
+                return Err(ShaderError::Compile { file: source_map.file().to_string(), stage, log: source_map.map_log(&log) });
+            }
         }
-
-        // handle errors
         Ok(id)
     }
 
-    pub fn from_vert_source(source: &CStr) -> Result<Shader, String> {
-        Shader::from_source(source, gl::VERTEX_SHADER)
+    pub fn from_vert_source(source: &CStr, source_map: &SourceMap) -> Result<Shader, ShaderError> {
+        Shader::from_source(source, ShaderStage::Vertex, source_map)
     }
 
-    pub fn from_frag_source(source: &CStr) -> Result<Shader, String> {
-        Shader::from_source(source, gl::FRAGMENT_SHADER)
+    pub fn from_frag_source(source: &CStr, source_map: &SourceMap) -> Result<Shader, ShaderError> {
+        Shader::from_source(source, ShaderStage::Fragment, source_map)
     }
 
     pub fn id(&self) -> GLuint {
         self.id
     }
 
-    unsafe fn check_compile_error(id: GLuint, kind: GLenum) {
+    unsafe fn check_compile_error(id: GLuint) -> Result<(), String> {
         let mut success = gl::FALSE as GLint;
-        let mut info_log = Vec::with_capacity(1024);
-        info_log.set_len(1024 - 1); // subtract 1 to skip the trailing null character
+        let mut info_log = vec![0u8; 1024];
         gl::GetShaderiv(id, gl::COMPILE_STATUS, &mut success);
         if success != gl::TRUE as GLint {
-            gl::GetShaderInfoLog(id, 1024, ptr::null_mut(), info_log.as_mut_ptr() as *mut GLchar);
-            println!("ERROR::SHADER_COMPILATION_ERROR of type: {}\n{}\n \
-                              -- --------------------------------------------------- -- ",
-                     kind,
-                     String::from_utf8(info_log).unwrap());
+            let mut length = 0;
+            gl::GetShaderInfoLog(id, 1024, &mut length, info_log.as_mut_ptr() as *mut GLchar);
+            info_log.truncate(length as usize);
+            return Err(String::from_utf8_lossy(&info_log).to_string());
         }
+        Ok(())
     }
 }
 
@@ -70,102 +197,404 @@ impl Drop for Shader {
 // =================================================================================================
 
 // =================================================================================================
+/// An active uniform or attribute, as the linked program has it
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveVariable {
+    pub location: GLint,
+    /// e.g. `gl::FLOAT_VEC3`
+    pub kind: GLenum,
+    /// the array length, 1 when it isn't one
+    #[allow(dead_code)]
+    pub size: GLint
+}
+
+/// The GLSL name of an active variable's type
+pub fn glsl_type_name(kind: GLenum) -> String {
+    match kind {
+        gl::BOOL => "bool".to_string(),
+        gl::INT => "int".to_string(),
+        gl::FLOAT => "float".to_string(),
+        gl::FLOAT_VEC2 => "vec2".to_string(),
+        gl::FLOAT_VEC3 => "vec3".to_string(),
+        gl::FLOAT_VEC4 => "vec4".to_string(),
+        gl::FLOAT_MAT3 => "mat3".to_string(),
+        gl::FLOAT_MAT4 => "mat4".to_string(),
+        gl::SAMPLER_2D => "sampler2D".to_string(),
+        gl::SAMPLER_CUBE => "samplerCube".to_string(),
+        _ => format!("type 0x{:x}", kind)
+    }
+}
+
+/// A value `ShaderProgram::set_uniform` can upload
+pub trait UniformValue {
+    /// for reporting a mismatch
+    const GLSL_TYPE: &'static str;
+
+    /// Whether a uniform of the type can be set with the value
+    fn accepts(kind: GLenum) -> bool;
+
+    unsafe fn upload(&self, location: GLint);
+}
+
+impl UniformValue for bool {
+    const GLSL_TYPE: &'static str = "bool";
+
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::BOOL || kind == gl::INT
+    }
+
+    unsafe fn upload(&self, location: GLint) {
+        gl::Uniform1i(location, *self as i32);
+    }
+}
+
+impl UniformValue for i32 {
+    const GLSL_TYPE: &'static str = "int";
+
+    // samplers are set to their texture unit
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::INT || kind == gl::BOOL || kind == gl::SAMPLER_2D || kind == gl::SAMPLER_CUBE
+    }
+
+    unsafe fn upload(&self, location: GLint) {
+        gl::Uniform1i(location, *self);
+    }
+}
+
+impl UniformValue for f32 {
+    const GLSL_TYPE: &'static str = "float";
+
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::FLOAT
+    }
+
+    unsafe fn upload(&self, location: GLint) {
+        gl::Uniform1f(location, *self);
+    }
+}
+
+impl UniformValue for Vector3<f32> {
+    const GLSL_TYPE: &'static str = "vec3";
+
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::FLOAT_VEC3
+    }
+
+    unsafe fn upload(&self, location: GLint) {
+        gl::Uniform3fv(location, 1, self.as_ptr());
+    }
+}
+
+impl UniformValue for Vector4<f32> {
+    const GLSL_TYPE: &'static str = "vec4";
+
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::FLOAT_VEC4
+    }
+
+    unsafe fn upload(&self, location: GLint) {
+        gl::Uniform4fv(location, 1, self.as_ptr());
+    }
+}
+
+impl UniformValue for Matrix4<f32> {
+    const GLSL_TYPE: &'static str = "mat4";
+
+    fn accepts(kind: GLenum) -> bool {
+        kind == gl::FLOAT_MAT4
+    }
+
+    unsafe fn upload(&self, location: GLint) {
+        gl::UniformMatrix4fv(location, 1, gl::FALSE, self.as_ptr());
+    }
+}
+
+thread_local! {
+    // reported by the setters, passed on to `ShaderReloadStatus` by `ShaderReloader`
+    static UNIFORM_ERRORS: RefCell<Vec<ShaderProgramError>> = const { RefCell::new(Vec::new()) };
+}
+
+// the program's gl id and what it was linked with, deleted along with the last `ShaderProgram`
+// using it
+struct LinkedProgram {
+    id: Cell<GLuint>,
+    name: String,
+    // array uniforms are in both as `name` and as each `name[i]`
+    uniforms: RefCell<HashMap<String, ActiveVariable>>,
+    attributes: RefCell<HashMap<String, ActiveVariable>>,
+    // uniform blocks and their binding points, bound again whenever the program is rebuilt
+    block_bindings: RefCell<Vec<(String, GLuint)>>,
+    // uniforms already reported, so a mistake made every frame is only reported once
+    reported: RefCell<HashSet<String>>
+}
+
+impl LinkedProgram {
+    fn new(id: GLuint, name: String) -> LinkedProgram {
+        let (uniforms, attributes) = unsafe {
+            (LinkedProgram::query_uniforms(id), LinkedProgram::query_attributes(id))
+        };
+        LinkedProgram {
+            id: Cell::new(id),
+            name,
+            uniforms: RefCell::new(uniforms),
+            attributes: RefCell::new(attributes),
+            block_bindings: RefCell::new(Vec::new()),
+            reported: RefCell::new(HashSet::new())
+        }
+    }
+
+    unsafe fn query_uniforms(id: GLuint) -> HashMap<String, ActiveVariable> {
+        let mut count: GLint = 0;
+        let mut max_length: GLint = 0;
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORMS, &mut count);
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+        let location = |name: &str| gl::GetUniformLocation(id, CString::new(name).unwrap().as_ptr());
+
+        let mut uniforms = HashMap::new();
+        for index in 0..count as GLuint {
+            let (name, size, kind) = LinkedProgram::read_active(max_length, |buffer_size, length, size, kind, name| {
+                gl::GetActiveUniform(id, index, buffer_size, length, size, kind, name)
+            });
+            // arrays are named after their first element
+            if name.ends_with("[0]") {
+                let base_name = &name[..name.len() - 3];
+                for element in 0..size {
+                    let element_name = format!("{}[{}]", base_name, element);
+                    uniforms.insert(element_name.clone(), ActiveVariable { location: location(&element_name), kind, size: 1 });
+                }
+                uniforms.insert(base_name.to_string(), ActiveVariable { location: location(&name), kind, size });
+            } else {
+                uniforms.insert(name.clone(), ActiveVariable { location: location(&name), kind, size });
+            }
+        }
+        // members of uniform blocks have no location, they're set through the block's buffer
+        uniforms.retain(|_name, uniform| uniform.location >= 0);
+        uniforms
+    }
+
+    unsafe fn query_attributes(id: GLuint) -> HashMap<String, ActiveVariable> {
+        let mut count: GLint = 0;
+        let mut max_length: GLint = 0;
+        gl::GetProgramiv(id, gl::ACTIVE_ATTRIBUTES, &mut count);
+        gl::GetProgramiv(id, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, &mut max_length);
+
+        let mut attributes = HashMap::new();
+        for index in 0..count as GLuint {
+            let (name, size, kind) = LinkedProgram::read_active(max_length, |buffer_size, length, size, kind, name| {
+                gl::GetActiveAttrib(id, index, buffer_size, length, size, kind, name)
+            });
+            let location = gl::GetAttribLocation(id, CString::new(name.as_bytes()).unwrap().as_ptr());
+            // built in inputs such as gl_VertexID have none
+            if location >= 0 {
+                attributes.insert(name, ActiveVariable { location, kind, size });
+            }
+        }
+        attributes
+    }
+
+    // the name, array size and type from glGetActiveUniform or glGetActiveAttrib
+    unsafe fn read_active<F>(max_length: GLint, get_active: F) -> (String, GLint, GLenum)
+        where F: Fn(GLsizei, *mut GLsizei, *mut GLint, *mut GLenum, *mut GLchar)
+    {
+        let mut name: Vec<u8> = vec![0; max_length.max(1) as usize];
+        let mut length: GLsizei = 0;
+        let mut size: GLint = 0;
+        let mut kind: GLenum = 0;
+        get_active(max_length, &mut length, &mut size, &mut kind, name.as_mut_ptr() as *mut GLchar);
+        name.truncate(length as usize);
+        (String::from_utf8_lossy(&name).to_string(), size, kind)
+    }
+
+    // takes over the rebuilt program, which is left with the old one to delete
+    fn swap(&self, other: &LinkedProgram) {
+        self.id.swap(&other.id);
+        self.uniforms.swap(&other.uniforms);
+        self.attributes.swap(&other.attributes);
+        self.reported.borrow_mut().clear();
+        for &(ref name, binding) in self.block_bindings.borrow().iter() {
+            self.bind_block(name, binding);
+        }
+    }
+
+    fn bind_block(&self, name: &str, binding: GLuint) {
+        let index = unsafe {
+            gl::GetUniformBlockIndex(self.id.get(), CString::new(name).unwrap().as_ptr())
+        };
+        if index == gl::INVALID_INDEX {
+            self.report(name, ShaderError::MissingUniform { name: name.to_string() });
+            return;
+        }
+        unsafe {
+            gl::UniformBlockBinding(self.id.get(), index, binding);
+        }
+    }
+
+    fn report(&self, name: &str, error: ShaderError) {
+        if !self.reported.borrow_mut().insert(name.to_string()) {
+            return;
+        }
+        // printed as well as shown in the debug ui by way of `ShaderReloadStatus`, which may be hidden
+        let error = ShaderProgramError { program: self.name.clone(), log: error.to_string() };
+        eprintln!("{}: {}", error.program, error.log);
+        UNIFORM_ERRORS.with(|errors| errors.borrow_mut().push(error));
+    }
+}
+
+impl Drop for LinkedProgram {
+    fn drop(&mut self) {
+        unsafe {
+            // should we detach shaders first
+            gl::DeleteProgram(self.id.get());
+        }
+    }
+}
+
 pub struct ShaderProgram {
-    id: GLuint
+    // shared with the variant cache, which swaps in the rebuilt program when the sources change
+    program: Rc<LinkedProgram>
 }
 
 impl ShaderProgram {
 
-    pub fn from_shader_files(resource_loader: &ResourceLoader, vertex_shader: &str, fragment_shader: &str) -> Result<ShaderProgram, String> {
-        let c_str_vert: CString = resource_loader.load_cstring(vertex_shader)?;
-        let vert = Shader::from_vert_source(&c_str_vert)?;
-
-        let c_str_frag = resource_loader.load_cstring(fragment_shader)?;
-        let frag = Shader::from_frag_source(&c_str_frag)?;
+    /// See `from_shader_files_with_defines`
+    pub fn from_shader_files(resource_loader: &ResourceLoader, vertex_shader: &str, fragment_shader: &str) -> Result<ShaderProgram, ShaderError> {
+        ShaderProgram::from_shader_files_with_defines(resource_loader, vertex_shader, fragment_shader, &[])
+    }
 
-        let program = ShaderProgram::from_shaders(&[vert, frag]);
-        return program;
+    /// The sources are run through `preprocess` with the defines, so `#include "lighting.glsl"`
+    /// and `#ifdef` work. Each set of defines is compiled once, asking again gets the same
+    /// program. Also watches the files, see `ShaderReloader`.
+    pub fn from_shader_files_with_defines(
+        resource_loader: &ResourceLoader,
+        vertex_shader: &str,
+        fragment_shader: &str,
+        defines: &[(&str, &str)]
+    ) -> Result<ShaderProgram, ShaderError> {
+        let mut defines: Vec<(String, String)> = defines.iter()
+            .map(|&(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        defines.sort();
+        let key = VariantKey { vertex_shader: vertex_shader.to_string(), fragment_shader: fragment_shader.to_string(), defines };
+        if let Some(program) = ProgramVariant::find(&key) {
+            return Ok(program);
+        }
+        let (program, files) = key.compile(&|name: &str| resource_loader.load_string(name))?;
+        ProgramVariant::add(key, &program, files, resource_loader);
+        Ok(program)
     }
 
+    #[allow(dead_code)]
+    pub fn from_shaders(shaders: &[Shader]) -> Result<ShaderProgram, ShaderError> {
+        ShaderProgram::link(shaders, None)
+    }
 
-    pub fn from_shaders(shaders: &[Shader]) -> Result<ShaderProgram, String> {
+    // the name is what problems with the program are reported against
+    fn link(shaders: &[Shader], name: Option<String>) -> Result<ShaderProgram, ShaderError> {
         let program_id = unsafe { gl::CreateProgram() };
 
         for shader in shaders {
             unsafe { gl::AttachShader(program_id, shader.id()); }
         }
 
-        unsafe {
+        let link_result = unsafe {
             gl::LinkProgram(program_id);
             ShaderProgram::check_compile_error(program_id)
-
-        }
-
-        // handle errros
+        };
 
         for shader in shaders {
             unsafe { gl::DetachShader(program_id, shader.id()); }
         }
 
+        if let Err(log) = link_result {
+            unsafe { gl::DeleteProgram(program_id); }
+            return Err(ShaderError::Link { log });
+        }
+        let name = name.unwrap_or_else(|| format!("program {}", program_id));
+        Ok(ShaderProgram { program: Rc::new(LinkedProgram::new(program_id, name)) })
+    }
 
-        Ok(ShaderProgram { id: program_id})
+    #[allow(dead_code)]
+    pub fn get_uniform_location(&self, uniform_name: &str) -> Result<i32, ShaderError> {
+        self.uniform(uniform_name)
+            .map(|uniform| uniform.location)
+            .ok_or_else(|| ShaderError::MissingUniform { name: uniform_name.to_string() })
     }
 
     #[allow(dead_code)]
-    pub fn get_uniform_location(&self, uniform_name: &String) -> Result<i32, String> {
-        unsafe {
-            return Ok(gl::GetUniformLocation(self.id, CString::new(uniform_name.as_bytes()).unwrap().as_ptr()));
+    pub fn uniform(&self, name: &str) -> Option<ActiveVariable> {
+        self.program.uniforms.borrow().get(name).cloned()
+    }
+
+    #[allow(dead_code)]
+    pub fn attribute(&self, name: &str) -> Option<ActiveVariable> {
+        self.program.attributes.borrow().get(name).cloned()
+    }
+
+    /// Reads the uniform block from whatever buffer is bound to the binding point, see
+    /// `gl::BindBufferBase`
+    pub fn bind_uniform_block(&self, name: &str, binding: GLuint) {
+        let mut block_bindings = self.program.block_bindings.borrow_mut();
+        if !block_bindings.iter().any(|&(ref bound_name, _binding)| bound_name == name) {
+            block_bindings.push((name.to_string(), binding));
+        }
+        self.program.bind_block(name, binding);
+    }
+
+    /// Uses the location looked up after linking. A name the program has no active uniform for,
+    /// or a value of the wrong type, is reported once to the log and the debug ui.
+    pub fn set_uniform<T: UniformValue>(&self, name: &CStr, value: &T) {
+        let name = name.to_string_lossy();
+        match self.uniform(&name) {
+            Some(uniform) if T::accepts(uniform.kind) => unsafe { value.upload(uniform.location) },
+            Some(uniform) => self.program.report(&name, ShaderError::UniformType {
+                name: name.to_string(),
+                glsl_type: glsl_type_name(uniform.kind),
+                value_type: T::GLSL_TYPE
+            }),
+            None => self.program.report(&name, ShaderError::MissingUniform { name: name.to_string() })
         }
     }
 
     #[allow(dead_code)]
     pub fn set_bool(&self, name: &CStr, value: bool) {
-        unsafe {
-            gl::Uniform1i(gl::GetUniformLocation(self.id, name.as_ptr()), value as i32);
-        }
+        self.set_uniform(name, &value);
     }
 
     #[allow(dead_code)]
     pub fn set_int(&self, name: &CStr, value: i32) {
-        unsafe {
-            gl::Uniform1i(gl::GetUniformLocation(self.id, name.as_ptr()), value);
-        }
+        self.set_uniform(name, &value);
     }
 
     #[allow(dead_code)]
     pub fn set_float(&self, name: &CStr, value: f32) {
-        unsafe {
-            gl::Uniform1f(gl::GetUniformLocation(self.id, name.as_ptr()), value);
-        }
+        self.set_uniform(name, &value);
     }
 
     pub fn set_vector3(&self, name: &CStr, value: &Vector3<f32>) {
-        unsafe {
-            gl::Uniform3fv(gl::GetUniformLocation(self.id, name.as_ptr()), 1, value.as_ptr());
-        }
+        self.set_uniform(name, value);
+    }
+
+    pub fn set_vector4(&self, name: &CStr, value: &Vector4<f32>) {
+        self.set_uniform(name, value);
     }
 
     #[allow(dead_code)]
     pub fn set_vec3(&self, name: &CStr, x: f32, y: f32, z: f32) {
-        unsafe {
-            gl::Uniform3f(gl::GetUniformLocation(self.id, name.as_ptr()), x, y, z);
-        }
+        self.set_uniform(name, &Vector3::new(x, y, z));
     }
 
     pub fn set_mat4(&self, name: &CStr, mat: &Matrix4<f32>) {
-        unsafe {
-            gl::UniformMatrix4fv(gl::GetUniformLocation(self.id, name.as_ptr()), 1, gl::FALSE, mat.as_ptr());
-        }
+        self.set_uniform(name, mat);
     }
 
     #[allow(dead_code)]
     pub fn id(&self) -> GLuint {
-        self.id
+        self.program.id.get()
     }
 
     pub fn start(&self) {
         unsafe {
-            gl::UseProgram(self.id);
+            gl::UseProgram(self.program.id.get());
         }
     }
 
@@ -175,27 +604,245 @@ impl ShaderProgram {
         }
     }
 
-    unsafe fn check_compile_error(id: GLuint) {
+    unsafe fn check_compile_error(id: GLuint) -> Result<(), String> {
         let mut success = gl::FALSE as GLint;
-        let mut info_log = Vec::with_capacity(1024);
-        info_log.set_len(1024 - 1); // subtract 1 to skip the trailing null character
+        let mut info_log = vec![0u8; 1024];
         gl::GetProgramiv(id, gl::LINK_STATUS, &mut success);
         if success != gl::TRUE as GLint {
-            gl::GetProgramInfoLog(id, 1024, ptr::null_mut(), info_log.as_mut_ptr() as *mut GLchar);
-            println!("ERROR::PROGRAM_LINKING_ERROR: {}\n \
-                      -- --------------------------------------------------- -- ",
-                     String::from_utf8(info_log).unwrap());
+            let mut length = 0;
+            gl::GetProgramInfoLog(id, 1024, &mut length, info_log.as_mut_ptr() as *mut GLchar);
+            info_log.truncate(length as usize);
+            return Err(String::from_utf8_lossy(&info_log).to_string());
         }
+        Ok(())
     }
 
 }
 
-impl Drop for ShaderProgram {
-    fn drop(&mut self) {
-        unsafe {
-            // should we detach shaders first
-            gl::DeleteProgram(self.id);
+// =================================================================================================
+
+// =================================================================================================
+thread_local! {
+    // programs are only ever used on the thread with the gl context
+    static PROGRAM_VARIANTS: RefCell<Vec<ProgramVariant>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct VariantKey {
+    vertex_shader: String,
+    fragment_shader: String,
+    // sorted, so the order they were given in doesn't matter
+    defines: Vec<(String, String)>
+}
+
+impl VariantKey {
+    fn name(&self) -> String {
+        let mut name = format!("{} + {}", self.vertex_shader, self.fragment_shader);
+        if !self.defines.is_empty() {
+            let defines: Vec<String> = self.defines.iter()
+                .map(|(define, value)| format!("{}={}", define, value))
+                .collect();
+            name.push_str(&format!(" [{}]", defines.join(", ")));
+        }
+        name
+    }
+
+    /// The program and every file that went into it
+    fn compile<F>(&self, load: &F) -> Result<(ShaderProgram, Vec<String>), ShaderError>
+        where F: Fn(&str) -> Result<String, String>
+    {
+        let vert_source = preprocess(&self.vertex_shader, &self.defines, load)?;
+        let vert = Shader::from_vert_source(&vert_source.source, &vert_source.source_map)?;
+
+        let frag_source = preprocess(&self.fragment_shader, &self.defines, load)?;
+        let frag = Shader::from_frag_source(&frag_source.source, &frag_source.source_map)?;
+
+        let program = ShaderProgram::link(&[vert, frag], Some(self.name()))?;
+        let mut files = vert_source.files;
+        for file in frag_source.files {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+        Ok((program, files))
+    }
+}
+
+/// A compiled program shared by everything asking for the same files and defines, rebuilt by
+/// `ShaderReloader` when any file that went into it changes
+struct ProgramVariant {
+    key: VariantKey,
+    program: Weak<LinkedProgram>,
+    files: Vec<String>,
+    modified: Option<SystemTime>
+}
+
+impl ProgramVariant {
+    fn find(key: &VariantKey) -> Option<ShaderProgram> {
+        PROGRAM_VARIANTS.with(|variants| {
+            variants.borrow().iter()
+                .filter(|variant| variant.key == *key)
+                .filter_map(|variant| variant.program.upgrade())
+                .next()
+                .map(|program| ShaderProgram { program })
+        })
+    }
+
+    fn add(key: VariantKey, program: &ShaderProgram, files: Vec<String>, resource_loader: &ResourceLoader) {
+        let mut variant = ProgramVariant { key, program: Rc::downgrade(&program.program), files, modified: None };
+        variant.modified = variant.last_modified(resource_loader);
+        PROGRAM_VARIANTS.with(|variants| {
+            let mut variants = variants.borrow_mut();
+            // dropped programs
+            variants.retain(|variant| variant.program.upgrade().is_some());
+            variants.push(variant);
+        });
+    }
+
+    fn last_modified(&self, resource_loader: &ResourceLoader) -> Option<SystemTime> {
+        self.files.iter()
+            .filter_map(|name| resource_loader.source_modified(name))
+            .max()
+    }
+
+    /// Swaps in the new program, the old one is kept when it fails to build
+    fn rebuild(&mut self, linked_program: &LinkedProgram, resource_loader: &ResourceLoader) -> Result<(), ShaderError> {
+        let (program, files) = self.key.compile(&|name: &str| resource_loader.load_source_string(name))?;
+        // the old one is deleted along with `program`
+        linked_program.swap(&program.program);
+        self.files = files;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderProgramError {
+    pub program: String,
+    pub log: String
+}
+
+/// How shader hot reloading is going, for the debug ui
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReloadStatus {
+    pub reloaded: u32,
+    pub last_reloaded: Option<String>,
+    /// programs still running their last working version, as their sources don't compile
+    pub errors: Vec<ShaderProgramError>,
+    /// uniforms set that the programs don't have, or with the wrong type, each reported once
+    pub uniform_errors: Vec<ShaderProgramError>
+}
+
+/// Rebuilds every program made by `ShaderProgram::from_shader_files` whose sources, or the files
+/// they include, have changed in the `res` tree of the checkout. Debug builds only.
+pub struct ShaderReloader {
+    resource_loader: Rc<ResourceLoader>,
+    since_last_check: f32
+}
+
+impl ShaderReloader {
+    // seconds between looking at the files
+    const CHECK_INTERVAL: f32 = 0.5;
+
+    pub fn new(resource_loader: Rc<ResourceLoader>) -> ShaderReloader {
+        ShaderReloader { resource_loader, since_last_check: 0.0 }
+    }
+}
+
+impl<'a> System<'a> for ShaderReloader {
+    type SystemData = (Read<'a, UpdateDeltaTime>,
+                       Write<'a, ShaderReloadStatus>);
+
+    fn run(&mut self, (dt, mut status): Self::SystemData) {
+        UNIFORM_ERRORS.with(|errors| status.uniform_errors.append(&mut errors.borrow_mut()));
+
+        self.since_last_check += dt.0;
+        if self.since_last_check < ShaderReloader::CHECK_INTERVAL {
+            return;
         }
+        self.since_last_check = 0.0;
+        if !self.resource_loader.can_watch() {
+            return;
+        }
+
+        let resource_loader = &self.resource_loader;
+        PROGRAM_VARIANTS.with(|variants| {
+            let mut variants = variants.borrow_mut();
+            for variant in variants.iter_mut() {
+                let linked_program = match variant.program.upgrade() {
+                    Some(linked_program) => linked_program,
+                    // dropped, pruned below
+                    None => continue
+                };
+                let modified = variant.last_modified(resource_loader);
+                if modified <= variant.modified {
+                    continue;
+                }
+                variant.modified = modified;
+                let name = variant.key.name();
+                status.errors.retain(|error| error.program != name);
+                match variant.rebuild(&linked_program, resource_loader) {
+                    Ok(()) => {
+                        // reported again if the new version still has them
+                        status.uniform_errors.retain(|error| error.program != name);
+                        status.reloaded += 1;
+                        status.last_reloaded = Some(name);
+                    },
+                    Err(error) => {
+                        status.errors.push(ShaderProgramError { program: name, log: error.to_string() })
+                    }
+                }
+            }
+            variants.retain(|variant| variant.program.upgrade().is_some());
+        });
     }
 }
 // =================================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_map() -> SourceMap {
+        let mut source_map = SourceMap::default();
+        source_map.push("shaders/a.frag", 1);
+        source_map.push("shaders/lib.glsl", 1);
+        source_map.push("shaders/lib.glsl", 2);
+        source_map.push("shaders/a.frag", 3);
+        source_map
+    }
+
+    #[test]
+    fn the_file_is_the_first_one_pushed() {
+        assert_eq!(source_map().file(), "shaders/a.frag");
+        assert_eq!(SourceMap::default().file(), "");
+    }
+
+    #[test]
+    fn maps_each_driver_format() {
+        let source_map = source_map();
+        // Mesa
+        assert_eq!(source_map.map_log("0:3(12): error: `x' undeclared"), "shaders/lib.glsl:2(12): error: `x' undeclared");
+        // Nvidia
+        assert_eq!(source_map.map_log("0(4) : error C1008: undefined variable \"x\""), "shaders/a.frag:3 : error C1008: undefined variable \"x\"");
+        // AMD and Intel on Windows
+        assert_eq!(source_map.map_log("ERROR: 0:2: 'x' : undeclared identifier"), "ERROR: shaders/lib.glsl:1: 'x' : undeclared identifier");
+    }
+
+    #[test]
+    fn maps_every_line_of_the_log() {
+        assert_eq!(source_map().map_log("0:1(1): error: a\n0:4(1): error: b"), "shaders/a.frag:1(1): error: a\nshaders/a.frag:3(1): error: b");
+    }
+
+    #[test]
+    fn leaves_lines_without_a_known_location_alone() {
+        let source_map = source_map();
+        for log_line in ["0:9(1): error: past the end", "0:0(1): error: before the start", "error: vec3 is not vec4", "texture2D: deprecated"].iter() {
+            assert_eq!(source_map.map_log(log_line), *log_line);
+        }
+    }
+
+    #[test]
+    fn skips_numbers_that_are_part_of_a_word() {
+        assert_eq!(source_map().map_log("vec4(1): 0:2(3): error"), "vec4(1): shaders/lib.glsl:1(3): error");
+    }
+}
@@ -0,0 +1,207 @@
+use std::ffi::CStr;
+use std::ptr;
+use super::camera::Camera;
+use super::cgmath::{ortho, Matrix4, Vector3};
+use super::cgmath::prelude::*;
+use super::components::{MultiPartModel, RawModel, SimpleTexture, TerrainPhysics, TerrainTexturePack, Transform};
+use super::debugui::{DebugInfo, DrawStats};
+use super::environment::Light;
+use super::frustum::Frustum;
+use super::gl;
+use super::gl::types::*;
+use super::renderers::{Attachment, EntityBatch, EntityBatches, Fbo, InstanceBuffer, RenderTarget, TerrainPatches};
+use super::resources::ResourceLoader;
+use super::shaders::ShaderProgram;
+use super::specs::{Read, ReadStorage, System, Write};
+use super::WindowSize;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShadowSettings {
+    /// how far from the camera shadows are drawn, the map covers twice this across
+    pub distance: f32,
+    /// depth offset to stop surfaces shadowing themselves
+    pub bias: f32
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        ShadowSettings {
+            distance: 150.0,
+            bias: 0.002
+        }
+    }
+}
+
+/// The depth texture rendered this frame and the matrix taking world space into it
+pub struct ShadowMap {
+    pub texture_id: GLuint,
+    pub light_space_matrix: Matrix4<f32>
+}
+
+impl Default for ShadowMap {
+    fn default() -> Self {
+        ShadowMap {
+            texture_id: 0,
+            light_space_matrix: Matrix4::identity()
+        }
+    }
+}
+
+/// Renders the depth of every entity and terrain tile as seen from the sun into `ShadowMap`
+pub struct ShadowRenderer {
+    shadow_shader: ShaderProgram,
+    fbo: Fbo,
+    instance_buffer: InstanceBuffer,
+    terrain_patches: TerrainPatches
+}
+
+impl<'a> System<'a> for ShadowRenderer {
+
+    type SystemData = (ReadStorage<'a, Transform>,
+                       ReadStorage<'a, SimpleTexture>,
+                       ReadStorage<'a, RawModel>,
+                       ReadStorage<'a, MultiPartModel>,
+                       ReadStorage<'a, TerrainTexturePack>,
+                       ReadStorage<'a, TerrainPhysics>,
+                       Read<'a, Camera>,
+                       Read<'a, Light>,
+                       Read<'a, ShadowSettings>,
+                       Read<'a, WindowSize>,
+                       Write<'a, ShadowMap>,
+                       Write<'a, DebugInfo>);
+
+    fn run(&mut self, (transform, simple_texture, model, multi_part_model, terrain_texture_pack, terrain_physics, camera, light, shadow_settings, window_size, mut shadow_map, mut debug_info): Self::SystemData) {
+        let light_space_matrix = ShadowRenderer::light_space_matrix(&camera, &light, &shadow_settings);
+        let frustum = Frustum::from_matrix(&light_space_matrix);
+        self.prepare();
+        self.shadow_shader.start();
+        unsafe {
+            self.shadow_shader.set_mat4(c_str!("light_space_matrix"), &light_space_matrix);
+        }
+
+        // only the texture's cut-out shape matters for depth, so batches can span materials
+        let batches = EntityBatches::collect(&frustum, &transform, &model, &simple_texture, &multi_part_model,
+                                             |model, texture| (model.vao_id, texture.texture_id));
+        // what the light sees isn't what the camera sees, so only the draws count
+        let mut stats = DrawStats::default();
+        for batch in batches.batches.values() {
+            self.draw_batch(&mut stats, batch);
+        }
+
+        use super::specs::Join;
+        for (model, terrain_texture_pack, terrain_physics) in (&model, &terrain_texture_pack, &terrain_physics).join() {
+            let translation = Vector3 { x: terrain_physics.x, y: 0.0, z: terrain_physics.z };
+            if !frustum.contains_box(&model.bounding_box.translate(translation)) {
+                continue;
+            }
+            self.draw_terrain(&camera, &frustum, &mut stats, model, terrain_texture_pack, terrain_physics);
+        }
+        debug_info.current_frame_draw_calls += stats.draw_calls;
+        debug_info.current_frame_triangle_count += stats.triangle_count;
+
+        self.shadow_shader.stop();
+        self.finish(&window_size);
+        shadow_map.texture_id = self.fbo.depth_texture();
+        shadow_map.light_space_matrix = light_space_matrix;
+    }
+}
+
+impl ShadowRenderer {
+
+    const VERTEX_SHADER: &'static str = "shaders/shadow.vert";
+    const FRAGMENT_SHADER: &'static str = "shaders/shadow.frag";
+    const SHADOW_MAP_SIZE: u32 = 2048;
+
+    pub fn new(resource_loader: &ResourceLoader) -> Result<ShadowRenderer, String> {
+        let shadow_shader = ShaderProgram::from_shader_files(resource_loader, ShadowRenderer::VERTEX_SHADER, ShadowRenderer::FRAGMENT_SHADER)
+            .map_err(|e| e.to_string())?;
+        let fbo = Fbo::new(ShadowRenderer::SHADOW_MAP_SIZE, ShadowRenderer::SHADOW_MAP_SIZE, Attachment::None, Attachment::Texture(gl::DEPTH_COMPONENT24))?;
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, fbo.depth_texture());
+            // filtered by hand in the shaders
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            // anything outside the map is at the far plane so never in shadow
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+            let border_colour = [1.0f32, 1.0, 1.0, 1.0];
+            gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, border_colour.as_ptr());
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        let shadow_renderer = ShadowRenderer { shadow_shader, fbo, instance_buffer: InstanceBuffer::create(), terrain_patches: TerrainPatches::default() };
+        shadow_renderer.shadow_shader.start();
+        unsafe {
+            shadow_renderer.shadow_shader.set_int(c_str!("texture_sampler"), 0);
+        }
+        shadow_renderer.shadow_shader.stop();
+        Ok(shadow_renderer)
+    }
+
+    /// An orthographic projection along the sun direction, covering `distance` around a point
+    /// ahead of the camera so most of the shadowed area is in view
+    fn light_space_matrix(camera: &Camera, light: &Light, shadow_settings: &ShadowSettings) -> Matrix4<f32> {
+        let distance = shadow_settings.distance;
+        let center = camera.position + camera.front * (distance * 0.5);
+        // the sun is far enough away to treat its light as parallel
+        let light_direction = (-light.position).normalize();
+        let up = if light_direction.y.abs() > 0.9 { Vector3::unit_z() } else { Vector3::unit_y() };
+        let eye = center + light_direction * (-distance * 2.0);
+        let light_view = Matrix4::look_at(eye, center, up);
+        let light_projection = ortho(-distance, distance, -distance, distance, 0.0, distance * 4.0);
+        light_projection * light_view
+    }
+
+    fn prepare(&self) {
+        self.fbo.bind();
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            // foliage is double sided so nothing can be culled
+            gl::Disable(gl::CULL_FACE);
+        }
+    }
+
+    fn finish(&self, window_size: &WindowSize) {
+        RenderTarget::Screen.bind(window_size);
+    }
+
+    fn draw_batch(&self, stats: &mut DrawStats, batch: &EntityBatch) {
+        let instance_count = batch.instance_count();
+        stats.draw_calls += 1;
+        stats.triangle_count += (batch.model.vertex_count/3 * instance_count) as i32;
+        unsafe {
+            gl::BindVertexArray(batch.model.vao_id);
+            gl::EnableVertexAttribArray(0); // positions
+            gl::EnableVertexAttribArray(1); // texture coords, for cut-out textures
+            self.instance_buffer.bind_attributes(); // transformation matrices
+            self.instance_buffer.store(&batch.instance_data);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, batch.texture.texture_id);
+            gl::DrawElementsInstanced(gl::TRIANGLES, batch.model.vertex_count as i32, gl::UNSIGNED_INT, ptr::null(), instance_count as i32);
+            gl::DisableVertexAttribArray(0);
+            gl::DisableVertexAttribArray(1);
+            InstanceBuffer::unbind_attributes();
+            gl::BindVertexArray(0);
+        }
+    }
+
+    // at the same levels of detail as the frame, so the ground doesn't shadow itself where the two differ
+    fn draw_terrain(&mut self, camera: &Camera, frustum: &Frustum, stats: &mut DrawStats, model: &RawModel,
+                    terrain_texture_pack: &TerrainTexturePack, terrain_physics: &TerrainPhysics) {
+        unsafe {
+            gl::BindVertexArray(model.vao_id);
+            gl::EnableVertexAttribArray(0); // positions
+            gl::EnableVertexAttribArray(1); // texture coords
+            // opaque, so nothing is cut out
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, terrain_texture_pack.background_texture.texture_id);
+        }
+        InstanceBuffer::set_constant(&Matrix4::from_translation(Vector3 { x: terrain_physics.x, y: 0.0, z: terrain_physics.z }));
+        self.terrain_patches.draw(model, terrain_physics, camera, frustum, stats);
+        unsafe {
+            gl::DisableVertexAttribArray(0);
+            gl::DisableVertexAttribArray(1);
+            gl::BindVertexArray(0);
+        }
+    }
+}
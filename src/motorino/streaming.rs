@@ -0,0 +1,172 @@
+use super::specs::{System, Entities, Entity, LazyUpdate, Read, ReadStorage, WriteStorage};
+use super::cgmath::Vector3;
+use super::models::Loader;
+use super::terrain::{Terrain, TerrainConfig};
+use super::noise::NoiseHeightMap;
+use super::components::{GridPosition, PlayerFlag, RawModel, TerrainPhysics, TerrainTexturePack, Transform};
+use super::scenery::Scenery;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Keeps the terrain chunks within `view_distance` of the player loaded, creating chunk
+/// entities and their scenery as the player approaches and deleting them once they are left behind
+pub struct TerrainStreamer {
+    // shared with the main loop, which releases what deleted chunks leave unused
+    loader: Rc<RefCell<Loader>>,
+    height_source: NoiseHeightMap,
+    config: TerrainConfig,
+    texture_pack: TerrainTexturePack,
+    scenery: Scenery,
+    view_distance: i32,
+    chunks: HashMap<(i32, i32), Entity>
+}
+
+impl TerrainStreamer {
+    // generating a chunk is slow so spread new ones over several frames
+    const CHUNKS_PER_FRAME: usize = 1;
+
+    /// Fails if chunks can't be made with `config`
+    pub fn new(loader: Rc<RefCell<Loader>>, config: TerrainConfig, height_source: NoiseHeightMap, scenery: Scenery, view_distance: i32) -> Result<TerrainStreamer, String> {
+        config.validate()?;
+        let texture_pack = TerrainTexturePack::new(&mut loader.borrow_mut(), "textures/grass.jpg", "textures/mud.jpg", "textures/grass-flowers.jpg", "textures/path.jpg", "textures/blend-map.jpg");
+        Ok(TerrainStreamer {
+            loader,
+            height_source,
+            config,
+            texture_pack,
+            scenery,
+            view_distance,
+            chunks: HashMap::new()
+        })
+    }
+
+    fn chunk_for_position(&self, position: &Vector3<f32>) -> (i32, i32) {
+        ((position.x / self.config.size).floor() as i32, (position.z / self.config.size).floor() as i32)
+    }
+
+    fn missing_chunks(chunks: &HashMap<(i32, i32), Entity>, center: (i32, i32), view_distance: i32) -> Vec<(i32, i32)> {
+        let mut missing = Vec::new();
+        for x in center.0 - view_distance..center.0 + view_distance + 1 {
+            for z in center.1 - view_distance..center.1 + view_distance + 1 {
+                if !chunks.contains_key(&(x, z)) {
+                    missing.push((x, z));
+                }
+            }
+        }
+        // nearest first so the ground under the player is never the one left waiting
+        missing.sort_by_key(|&(x, z)| (x - center.0).abs().max((z - center.1).abs()));
+        missing
+    }
+
+    fn distant_chunks(chunks: &HashMap<(i32, i32), Entity>, center: (i32, i32), view_distance: i32) -> Vec<(i32, i32)> {
+        // one chunk of slack so walking back and forth over a border doesn't regenerate it
+        let unload_distance = view_distance + 1;
+        chunks.keys()
+            .filter(|&&(x, z)| (x - center.0).abs() > unload_distance || (z - center.1).abs() > unload_distance)
+            .cloned()
+            .collect()
+    }
+}
+
+impl<'a> System<'a> for TerrainStreamer {
+    type SystemData = (Entities<'a>,
+                       ReadStorage<'a, PlayerFlag>,
+                       ReadStorage<'a, Transform>,
+                       WriteStorage<'a, GridPosition>,
+                       WriteStorage<'a, TerrainPhysics>,
+                       WriteStorage<'a, RawModel>,
+                       WriteStorage<'a, TerrainTexturePack>,
+                       Read<'a, LazyUpdate>);
+
+    fn run(&mut self, (entities, player_flag, transform, mut grid_position, mut terrain_physics, mut raw_model, mut terrain_texture_pack, lazy): Self::SystemData) {
+        use super::specs::Join;
+        let center = match (&player_flag, &transform).join().next() {
+            Some((_player_flag, transform)) => self.chunk_for_position(&transform.position),
+            None => return
+        };
+
+        let distant: HashSet<(i32, i32)> = TerrainStreamer::distant_chunks(&self.chunks, center, self.view_distance).into_iter().collect();
+        if !distant.is_empty() {
+            for chunk in distant.iter() {
+                self.chunks.remove(chunk);
+            }
+            // the chunk and its scenery all carry its grid position
+            for (entity, grid_position) in (&*entities, &grid_position).join() {
+                if distant.contains(&(grid_position.x, grid_position.z)) {
+                    entities.delete(entity).ok();
+                }
+            }
+        }
+
+        // with nothing loaded yet there is nothing to stand on, so load everything at once
+        let chunks_to_load = if self.chunks.is_empty() { usize::MAX } else { TerrainStreamer::CHUNKS_PER_FRAME };
+        for (x, z) in TerrainStreamer::missing_chunks(&self.chunks, center, self.view_distance).into_iter().take(chunks_to_load) {
+            let terrain = match Terrain::new(&mut self.loader.borrow_mut(), x, z, &self.config, &self.height_source) {
+                Ok(terrain) => terrain,
+                // the config was checked when the streamer was made
+                Err(_) => continue
+            };
+            self.scenery.place(GridPosition { x, z }, &terrain, &entities, &lazy);
+            let entity = entities.build_entity()
+                .with(GridPosition { x, z }, &mut grid_position)
+                .with(TerrainPhysics { heights: terrain.heights, x: terrain.x, z: terrain.z, size: terrain.config.size }, &mut terrain_physics)
+                .with(terrain.raw_model, &mut raw_model)
+                .with(self.texture_pack.clone(), &mut terrain_texture_pack)
+                .build();
+            self.chunks.insert((x, z), entity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::specs::{Builder, World};
+
+    // the square of chunks within `radius` of `center`, all loaded
+    fn loaded(center: (i32, i32), radius: i32) -> HashMap<(i32, i32), Entity> {
+        let mut world = World::new();
+        let mut chunks = HashMap::new();
+        for x in center.0 - radius..center.0 + radius + 1 {
+            for z in center.1 - radius..center.1 + radius + 1 {
+                chunks.insert((x, z), world.create_entity().build());
+            }
+        }
+        chunks
+    }
+
+    #[test]
+    fn everything_within_the_view_distance_is_missing_at_first() {
+        let missing = TerrainStreamer::missing_chunks(&HashMap::new(), (3, -2), 2);
+        assert_eq!(missing.len(), 25);
+        assert_eq!(missing[0], (3, -2));
+        assert!(missing.iter().all(|&(x, z)| (x - 3).abs() <= 2 && (z + 2).abs() <= 2));
+    }
+
+    #[test]
+    fn missing_chunks_are_nearest_first() {
+        let missing = TerrainStreamer::missing_chunks(&HashMap::new(), (0, 0), 3);
+        let rings: Vec<i32> = missing.iter().map(|&(x, z)| x.abs().max(z.abs())).collect();
+        let mut sorted = rings.clone();
+        sorted.sort();
+        assert_eq!(rings, sorted);
+    }
+
+    #[test]
+    fn moving_one_chunk_loads_one_new_row() {
+        let chunks = loaded((0, 0), 2);
+        let mut missing = TerrainStreamer::missing_chunks(&chunks, (1, 0), 2);
+        missing.sort();
+        assert_eq!(missing, vec![(3, -2), (3, -1), (3, 0), (3, 1), (3, 2)]);
+    }
+
+    #[test]
+    fn chunks_are_kept_one_past_the_view_distance() {
+        let chunks = loaded((0, 0), 2);
+        assert!(TerrainStreamer::distant_chunks(&chunks, (1, 0), 2).is_empty());
+        let mut distant = TerrainStreamer::distant_chunks(&chunks, (2, 0), 2);
+        distant.sort();
+        assert_eq!(distant, vec![(-2, -2), (-2, -1), (-2, 0), (-2, 1), (-2, 2)]);
+    }
+}
@@ -10,111 +10,278 @@ use super::specs::ReadStorage;
 use super::components::GridPosition;
 use super::components::TerrainPhysics;
 
-pub type Heights = [[f32; Terrain::VERTEX_COUNT as usize]; Terrain::VERTEX_COUNT as usize];
+/// Describes the footprint and detail of one terrain tile
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainConfig {
+    /// length of a side in world units
+    pub size: f32,
+    /// number of vertices along a side, one more than a multiple of `TerrainLod::PATCH_QUADS`
+    /// so the tile splits into level of detail patches
+    pub vertex_count: u32,
+    pub max_height: f32
+}
+
+impl TerrainConfig {
+    /// Tiles are only drawn as whole level of detail patches
+    pub fn validate(&self) -> Result<(), String> {
+        if !TerrainLod::supports(self.vertex_count) {
+            return Err(format!("Terrain vertex count must be one more than a multiple of {}, not {}", TerrainLod::PATCH_QUADS, self.vertex_count));
+        }
+        Ok(())
+    }
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            size: 800.0,
+            vertex_count: 257,
+            max_height: 40.0
+        }
+    }
+}
+
+/// Height of every vertex of a tile, indexed by grid x then z
+#[derive(Clone, Debug)]
+pub struct Heights {
+    vertex_count: u32,
+    values: Vec<f32>
+}
+
+impl Heights {
+    pub fn new(vertex_count: u32) -> Heights {
+        Heights { vertex_count, values: vec![0.0; (vertex_count * vertex_count) as usize] }
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    /// Coordinates outside the grid are clamped to its edge
+    pub fn get(&self, x: i32, z: i32) -> f32 {
+        let last = self.vertex_count as i32 - 1;
+        let x = x.max(0).min(last);
+        let z = z.max(0).min(last);
+        self.values[(x * self.vertex_count as i32 + z) as usize]
+    }
+
+    fn set(&mut self, x: u32, z: u32, height: f32) {
+        self.values[(x * self.vertex_count + z) as usize] = height;
+    }
+}
+
+/// Anything terrain heights can be generated from
+pub trait HeightSource {
+    /// Height in 0..1 for a vertex, given both its normalized position within the tile and its
+    /// position in the world so a source can either repeat per tile or span the whole world
+    fn get_height(&self, u: f32, v: f32, world_x: f32, world_z: f32) -> f32;
+}
 
-#[derive(Copy, Clone)]
+impl HeightSource for DynamicImage {
+    // interpolates between pixels so maps of any resolution can be used for any vertex count.
+    // The image x runs along the tile's z and the image y along its x, as the maps were drawn for.
+    fn get_height(&self, u: f32, v: f32, _world_x: f32, _world_z: f32) -> f32 {
+        let max_x = (self.width() - 1) as f32;
+        let max_y = (self.height() - 1) as f32;
+        let x = v.clamp(0.0, 1.0) * max_x;
+        let y = u.clamp(0.0, 1.0) * max_y;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let x1 = (x0 + 1.0).min(max_x);
+        let y1 = (y0 + 1.0).min(max_y);
+        let fx = x - x0;
+        let fy = y - y0;
+        let top = get_pixel_height(self, x0, y0) * (1.0 - fx) + get_pixel_height(self, x1, y0) * fx;
+        let bottom = get_pixel_height(self, x0, y1) * (1.0 - fx) + get_pixel_height(self, x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn get_pixel_height(height_map: &DynamicImage, x: f32, y: f32) -> f32 {
+    let rgba = height_map.get_pixel(x as u32, y as u32);
+    let r = rgba.data[0] as f32;
+    let g = rgba.data[1] as f32;
+    let b = rgba.data[2] as f32;
+    (r * g * b) / (255.0 * 255.0 * 255.0)
+}
+
+/// Geo-mipmapping: tiles are drawn as square patches, each using every `2^level`th vertex
+/// depending on its distance from the camera. Where a neighbour is one level coarser the
+/// odd vertices along the shared edge are snapped onto the even ones so the edges match.
+pub struct TerrainLod;
+
+impl TerrainLod {
+    pub const PATCH_QUADS: u32 = 32;
+    /// from every vertex down to only the patch corners
+    pub const LEVELS: u32 = 6;
+    // in patch widths, needs to be at least one for neighbours to stay within a level of each other
+    const LOD_DISTANCE: f32 = 3.0;
+
+    // sides of a patch, used as bits of the stitch mask
+    pub const NORTH: u32 = 1;
+    pub const EAST: u32 = 2;
+    pub const SOUTH: u32 = 4;
+    pub const WEST: u32 = 8;
+    pub const STITCH_COMBINATIONS: u32 = 16;
+
+    pub fn supports(vertex_count: u32) -> bool {
+        vertex_count > 1 && vertex_count % TerrainLod::PATCH_QUADS == 1
+    }
+
+    /// Level for the patch with its corner at `patch_x`, `patch_z`. Levels change at distances
+    /// doubling from `LOD_DISTANCE` patch widths so neighbours are never more than a level apart
+    pub fn level_for_patch(camera_x: f32, camera_z: f32, patch_x: f32, patch_z: f32, patch_size: f32) -> u32 {
+        let dx = patch_x + patch_size * 0.5 - camera_x;
+        let dz = patch_z + patch_size * 0.5 - camera_z;
+        let distance = (dx * dx + dz * dz).sqrt();
+        let lod_distance = patch_size * TerrainLod::LOD_DISTANCE;
+        if distance < lod_distance {
+            0
+        } else {
+            ((distance / lod_distance).log2() as u32 + 1).min(TerrainLod::LEVELS - 1)
+        }
+    }
+
+    /// Indices for one patch relative to its corner vertex, to be drawn with that as base vertex
+    pub fn patch_indices(vertex_count: u32, level: u32, stitch_mask: u32) -> Vec<u32> {
+        let quads = TerrainLod::PATCH_QUADS;
+        let step = 1 << level;
+        let coarse_step = step * 2;
+        // the coarsest level only has corners, nothing to snap
+        let stitch_mask = if level + 1 < TerrainLod::LEVELS { stitch_mask } else { 0 };
+        let snap = |x: u32, z: u32| -> u32 {
+            let mut x = x;
+            let mut z = z;
+            if (z == 0 && stitch_mask & TerrainLod::NORTH != 0) || (z == quads && stitch_mask & TerrainLod::SOUTH != 0) {
+                x -= x % coarse_step;
+            }
+            if (x == 0 && stitch_mask & TerrainLod::WEST != 0) || (x == quads && stitch_mask & TerrainLod::EAST != 0) {
+                z -= z % coarse_step;
+            }
+            z * vertex_count + x
+        };
+
+        let cells = quads / step;
+        let mut indices = Vec::with_capacity((cells * cells * 6) as usize);
+        for gz in 0..cells {
+            for gx in 0..cells {
+                let x = gx * step;
+                let z = gz * step;
+                let top_left = snap(x, z);
+                let top_right = snap(x + step, z);
+                let bottom_left = snap(x, z + step);
+                let bottom_right = snap(x + step, z + step);
+                for triangle in [[top_left, bottom_left, top_right], [top_right, bottom_left, bottom_right]].iter() {
+                    // snapping collapses some triangles to a line
+                    if triangle[0] != triangle[1] && triangle[1] != triangle[2] && triangle[0] != triangle[2] {
+                        indices.extend_from_slice(triangle);
+                    }
+                }
+            }
+        }
+        indices
+    }
+}
+
+#[derive(Clone)]
 pub struct Terrain {
     pub x: f32,
     pub z: f32,
+    pub config: TerrainConfig,
     pub raw_model: RawModel,
     pub heights: Heights,
 }
 
 impl Terrain {
-    pub const SIZE: u32 = 800;
-    const VERTEX_COUNT: u32 = 256;
-    const MAX_HEIGHT: f32 = 40.0;
-
-    pub fn new(loader: &mut Loader, gridx: u32, gridz: u32, height_map: &DynamicImage) -> Terrain {
-        let (raw_model, heights) = Terrain::generate_terrain(loader, &height_map);
-        Terrain { x: (gridx * Terrain::SIZE) as f32,
-            z: (gridz * Terrain::SIZE) as f32,
+
+    pub fn new<H: HeightSource>(loader: &mut Loader, gridx: i32, gridz: i32, config: &TerrainConfig, height_source: &H) -> Result<Terrain, String> {
+        config.validate()?;
+        let x = gridx as f32 * config.size;
+        let z = gridz as f32 * config.size;
+        let (raw_model, heights) = Terrain::generate_terrain(loader, config, x, z, height_source);
+        Ok(Terrain { x,
+            z,
+            config: *config,
             raw_model,
             heights
-        }
+        })
     }
 
-    pub fn generate_terrain(loader: &mut Loader, height_map: &DynamicImage) -> (RawModel, Heights) {
-        assert!(height_map.height() >= Terrain::VERTEX_COUNT);
-
-        let mut vertices = [0.0; (Terrain::VERTEX_COUNT * Terrain::VERTEX_COUNT * 3) as usize];
-        let mut normals = [0.0; (Terrain::VERTEX_COUNT * Terrain::VERTEX_COUNT * 3) as usize];
-        let mut texture_coords = [0.0; (Terrain::VERTEX_COUNT * Terrain::VERTEX_COUNT * 2) as usize];
-
-        let mut indices = [0 as u32; (6 * (Terrain::VERTEX_COUNT - 1) * (Terrain::VERTEX_COUNT - 1)) as usize];
-        let mut vertex_pointer = 0;
-        let mut heights: Heights = [[0.0; Terrain::VERTEX_COUNT as usize]; Terrain::VERTEX_COUNT as usize];
-
-        for i in 0..Terrain::VERTEX_COUNT {
-            for j in 0..Terrain::VERTEX_COUNT {
-                vertices[(vertex_pointer * 3)] = j as f32 / (Terrain::VERTEX_COUNT - 1) as f32 * Terrain::SIZE as f32;
-                let height = Terrain::get_height(&height_map, i, j);
-                heights[j as usize][i as usize] = height;
-                vertices[(vertex_pointer * 3) + 1] = height;
-                vertices[(vertex_pointer * 3) + 2] = i as f32 / (Terrain::VERTEX_COUNT - 1) as f32 * Terrain::SIZE as f32;
-
-                let normal = Terrain::calculate_normal(height_map, i, j);
-                normals[(vertex_pointer * 3)] = normal.x;
-                normals[(vertex_pointer * 3) + 1] = normal.y;
-                normals[(vertex_pointer * 3) + 2] = normal.z;
-                texture_coords[(vertex_pointer * 2)] = j as f32 / (Terrain::VERTEX_COUNT - 1) as f32;
-                texture_coords[(vertex_pointer * 2) + 1] = i as f32 / (Terrain::VERTEX_COUNT - 1) as f32;
-                vertex_pointer += 1;
+    pub fn generate_terrain<H: HeightSource>(loader: &mut Loader, config: &TerrainConfig, origin_x: f32, origin_z: f32, height_source: &H) -> (RawModel, Heights) {
+        let vertex_count = config.vertex_count;
+        let bordered_heights = Terrain::sample_bordered_heights(config, origin_x, origin_z, height_source);
+        let mut heights = Heights::new(vertex_count);
+        for i in 0..vertex_count {
+            for j in 0..vertex_count {
+                heights.set(j, i, bordered_heights.get(j as i32 + 1, i as i32 + 1));
             }
         }
 
-        let mut pointer = 0;
-        for gz in 0..Terrain::VERTEX_COUNT - 1 {
-            for gx in 0..Terrain::VERTEX_COUNT - 1 {
-                let top_left = (gz * Terrain::VERTEX_COUNT) + gx;
-                let top_right = top_left + 1;
-                let bottom_left = ((gz + 1) * Terrain::VERTEX_COUNT) + gx;
-                let bottom_right = bottom_left + 1;
-                indices[pointer] = top_left as u32;
-                indices[pointer + 1] = bottom_left as u32;
-                indices[pointer + 2] = top_right as u32;
-                indices[pointer + 3] = top_right as u32;
-                indices[pointer + 4] = bottom_left as u32;
-                indices[pointer + 5] = bottom_right as u32;
-                pointer += 6;
+        let count = (vertex_count * vertex_count) as usize;
+        let mut vertices = Vec::with_capacity(count * 3);
+        let mut normals = Vec::with_capacity(count * 3);
+        let mut texture_coords = Vec::with_capacity(count * 2);
+        let grid_square_size = config.size / (vertex_count - 1) as f32;
+
+        for i in 0..vertex_count {
+            for j in 0..vertex_count {
+                vertices.push(j as f32 * grid_square_size);
+                vertices.push(heights.get(j as i32, i as i32));
+                vertices.push(i as f32 * grid_square_size);
+
+                let normal = Terrain::calculate_normal(&bordered_heights, grid_square_size, j as i32 + 1, i as i32 + 1);
+                normals.push(normal.x);
+                normals.push(normal.y);
+                normals.push(normal.z);
+                texture_coords.push(j as f32 / (vertex_count - 1) as f32);
+                texture_coords.push(i as f32 / (vertex_count - 1) as f32);
             }
         }
-        (loader.load_to_vao(&vertices, &texture_coords, &normals, &indices), heights)
+
+        // tiles are split into patches drawn with the renderer's shared index buffer for each level
+        // of detail, so only need their vertices
+        (loader.load_to_vao(&vertices, &texture_coords, &normals, &[]), heights)
     }
 
-    fn get_height(height_map: &DynamicImage, x: u32, z: u32) -> f32 {
-        if x >= height_map.height() || z >= height_map.height() {
-            return 0.0;
+    // a vertex past each edge as well, so the normals there slope the same way as the neighbouring
+    // tile's. A source spanning the world carries on across the border and an image is clamped.
+    fn sample_bordered_heights<H: HeightSource>(config: &TerrainConfig, origin_x: f32, origin_z: f32, height_source: &H) -> Heights {
+        let vertex_count = config.vertex_count;
+        let mut bordered_heights = Heights::new(vertex_count + 2);
+        for i in 0..vertex_count + 2 {
+            for j in 0..vertex_count + 2 {
+                let u = (j as f32 - 1.0) / (vertex_count - 1) as f32;
+                let v = (i as f32 - 1.0) / (vertex_count - 1) as f32;
+                let height = height_source.get_height(u, v, origin_x + u * config.size, origin_z + v * config.size);
+                bordered_heights.set(j, i, height * config.max_height);
+            }
         }
-        let rgba = height_map.get_pixel(x, z);
-        let r = rgba.data[0] as f32;
-        let g = rgba.data[1] as f32;
-        let b = rgba.data[2] as f32;
-        let normalized_height = (r * g * b) / (255.0 * 255.0 * 255.0);
-        normalized_height * Terrain::MAX_HEIGHT
-    }
-
-    fn calculate_normal(height_map: &DynamicImage, x: u32, z: u32) -> Vector3<f32> {
-        let height_l = Terrain::get_height(height_map, if x == 0 {0} else {x-1} , z);
-        let height_r = Terrain::get_height(height_map, x + 1, z);
-        let height_d = Terrain::get_height(height_map, x, if z == 0 {0} else {z-1});
-        let height_u = Terrain::get_height(height_map, x, z + 1);
+        bordered_heights
+    }
+
+    fn calculate_normal(heights: &Heights, grid_square_size: f32, x: i32, z: i32) -> Vector3<f32> {
+        let height_l = heights.get(x - 1, z);
+        let height_r = heights.get(x + 1, z);
+        let height_d = heights.get(x, z - 1);
+        let height_u = heights.get(x, z + 1);
+        // central differences span two grid squares
         let normal = Vector3 {
             x: height_l - height_r,
-            y: 2.0,
+            y: 2.0 * grid_square_size,
             z: height_d - height_u,
         };
         normal.normalize()
     }
 
-    pub fn get_height_of_terrain(heights: &Heights, world_x: f32, world_z: f32, terrain_origin_x: f32, terrain_origin_z: f32) -> f32 {
+    pub fn get_height_of_terrain(heights: &Heights, terrain_size: f32, world_x: f32, world_z: f32, terrain_origin_x: f32, terrain_origin_z: f32) -> f32 {
         let terrain_x = world_x - terrain_origin_x;
         let terrain_z = world_z - terrain_origin_z;
-        let grid_square_size = Terrain::SIZE as f32 / (Terrain::VERTEX_COUNT as f32 - 1.0);
+        let vertex_count = heights.vertex_count() as i32;
+        let grid_square_size = terrain_size / (vertex_count as f32 - 1.0);
         let grid_x = (terrain_x / grid_square_size).floor() as i32;
         let grid_z = (terrain_z / grid_square_size).floor() as i32;
 
-        if grid_x >= Terrain::VERTEX_COUNT as i32 - 1 || grid_z >= Terrain::VERTEX_COUNT as i32 - 1 || grid_x < 0 || grid_z < 0 {
+        if grid_x >= vertex_count - 1 || grid_z >= vertex_count - 1 || grid_x < 0 || grid_z < 0 {
             return 0.0;
         }
 
@@ -122,15 +289,15 @@ impl Terrain {
         let normalized_cell_coord_x = (terrain_x % grid_square_size) / grid_square_size;
         let normalized_cell_coord_z = (terrain_z % grid_square_size) / grid_square_size;
         if normalized_cell_coord_x <= (1.0 - normalized_cell_coord_z) {
-            return Terrain::barry_centric(Vector3{x: 0.0, y: heights[grid_x as usize][grid_z as usize], z: 0.0},
-                                         Vector3{x: 1.0, y: heights[(grid_x + 1) as usize][grid_z as usize], z: 0.0},
-                                         Vector3{x: 0.0, y: heights[grid_x as usize][(grid_z + 1) as usize], z: 1.0},
-                                         Vector2{x: normalized_cell_coord_x, y: normalized_cell_coord_z});
+            Terrain::barry_centric(Vector3{x: 0.0, y: heights.get(grid_x, grid_z), z: 0.0},
+                                   Vector3{x: 1.0, y: heights.get(grid_x + 1, grid_z), z: 0.0},
+                                   Vector3{x: 0.0, y: heights.get(grid_x, grid_z + 1), z: 1.0},
+                                   Vector2{x: normalized_cell_coord_x, y: normalized_cell_coord_z})
         } else {
-            return Terrain::barry_centric(Vector3{x: 1.0, y: heights[(grid_x + 1) as usize][grid_z as usize], z: 0.0},
-                                         Vector3{x: 1.0, y: heights[(grid_x + 1) as usize][(grid_z + 1) as usize], z: 1.0},
-                                         Vector3{x: 0.0, y: heights[grid_x as usize][(grid_z + 1) as usize], z: 1.0},
-                                         Vector2{x: normalized_cell_coord_x, y: normalized_cell_coord_z});
+            Terrain::barry_centric(Vector3{x: 1.0, y: heights.get(grid_x + 1, grid_z), z: 0.0},
+                                   Vector3{x: 1.0, y: heights.get(grid_x + 1, grid_z + 1), z: 1.0},
+                                   Vector3{x: 0.0, y: heights.get(grid_x, grid_z + 1), z: 1.0},
+                                   Vector2{x: normalized_cell_coord_x, y: normalized_cell_coord_z})
         }
 
     }
@@ -140,22 +307,140 @@ impl Terrain {
         let l1 = ((p2.z - p3.z) * (pos.x - p3.x) + (p3.x - p2.x) * (pos.y - p3.z)) / det;
         let l2 = ((p3.z - p1.z) * (pos.x - p3.x) + (p1.x - p3.x) * (pos.y - p3.z)) / det;
         let l3 = 1.0 - l1 - l2;
-        return l1 * p1.y + l2 * p2.y + l3 * p3.y;
+        l1 * p1.y + l2 * p2.y + l3 * p3.y
     }
 
     pub fn get_height_for_position(grid_position: &ReadStorage<GridPosition>, terrain_physics: &ReadStorage<TerrainPhysics>, x: f32, z: f32) -> f32 {
         let mut height = 0.0;
         use super::specs::Join;
-        for (grid_position, terrain_physics) in (grid_position, terrain_physics).join() {
-            let terrain_size = Terrain::SIZE as i32;
-            let terrain_x_orig = (grid_position.x * terrain_size) as f32;
-            let terrain_z_orig = (grid_position.z * terrain_size) as f32;
-            if x >= terrain_x_orig && x < terrain_x_orig + terrain_size as f32 &&
-                z >= terrain_z_orig && z < terrain_z_orig + terrain_size as f32 {
-                height = Terrain::get_height_of_terrain(&terrain_physics.heights, x, z, terrain_x_orig, terrain_z_orig);
+        for (_grid_position, terrain_physics) in (grid_position, terrain_physics).join() {
+            let terrain_size = terrain_physics.size;
+            let terrain_x_orig = terrain_physics.x;
+            let terrain_z_orig = terrain_physics.z;
+            if x >= terrain_x_orig && x < terrain_x_orig + terrain_size &&
+                z >= terrain_z_orig && z < terrain_z_orig + terrain_size {
+                height = Terrain::get_height_of_terrain(&terrain_physics.heights, terrain_size, x, z, terrain_x_orig, terrain_z_orig);
                 break
             }
         }
         height
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // heights that keep changing slope across tile borders
+    struct Hills;
+
+    impl HeightSource for Hills {
+        fn get_height(&self, _u: f32, _v: f32, world_x: f32, world_z: f32) -> f32 {
+            (world_x * 0.01).sin() * (world_z * 0.013).cos() * 0.5 + 0.5
+        }
+    }
+
+    const VERTEX_COUNT: u32 = TerrainLod::PATCH_QUADS * 2 + 1;
+
+    fn grid_position(index: u32) -> (u32, u32) {
+        (index % VERTEX_COUNT, index / VERTEX_COUNT)
+    }
+
+    #[test]
+    fn configs_that_dont_split_into_patches_are_rejected() {
+        let config = TerrainConfig::default();
+        assert!(config.validate().is_ok());
+        assert!(TerrainConfig { vertex_count: VERTEX_COUNT, ..config }.validate().is_ok());
+        assert!(TerrainConfig { vertex_count: 256, ..config }.validate().is_err());
+        assert!(TerrainConfig { vertex_count: 1, ..config }.validate().is_err());
+    }
+
+    #[test]
+    fn only_whole_patches_are_supported() {
+        assert!(TerrainLod::supports(TerrainLod::PATCH_QUADS + 1));
+        assert!(TerrainLod::supports(257));
+        assert!(!TerrainLod::supports(256));
+        assert!(!TerrainLod::supports(1));
+        assert!(!TerrainLod::supports(0));
+    }
+
+    #[test]
+    fn each_level_halves_the_quads_along_a_side() {
+        for level in 0..TerrainLod::LEVELS {
+            let cells = TerrainLod::PATCH_QUADS >> level;
+            let indices = TerrainLod::patch_indices(VERTEX_COUNT, level, 0);
+            assert_eq!(indices.len() as u32, cells * cells * 6);
+            let step = 1 << level;
+            for &index in indices.iter() {
+                let (x, z) = grid_position(index);
+                assert!(x <= TerrainLod::PATCH_QUADS && z <= TerrainLod::PATCH_QUADS);
+                assert!(x % step == 0 && z % step == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn stitched_edges_only_use_the_coarser_vertices() {
+        let quads = TerrainLod::PATCH_QUADS;
+        for level in 0..TerrainLod::LEVELS - 1 {
+            let coarse_step = 2 << level;
+            for stitch_mask in 0..TerrainLod::STITCH_COMBINATIONS {
+                for &index in TerrainLod::patch_indices(VERTEX_COUNT, level, stitch_mask).iter() {
+                    let (x, z) = grid_position(index);
+                    if (z == 0 && stitch_mask & TerrainLod::NORTH != 0) || (z == quads && stitch_mask & TerrainLod::SOUTH != 0) {
+                        assert_eq!(x % coarse_step, 0, "level {} mask {}", level, stitch_mask);
+                    }
+                    if (x == 0 && stitch_mask & TerrainLod::WEST != 0) || (x == quads && stitch_mask & TerrainLod::EAST != 0) {
+                        assert_eq!(z % coarse_step, 0, "level {} mask {}", level, stitch_mask);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stitching_drops_the_collapsed_triangles() {
+        let full = TerrainLod::patch_indices(VERTEX_COUNT, 0, 0);
+        // every other quad along the edge loses one triangle
+        let stitched = TerrainLod::patch_indices(VERTEX_COUNT, 0, TerrainLod::NORTH);
+        assert_eq!(full.len() - stitched.len(), (TerrainLod::PATCH_QUADS / 2 * 3) as usize);
+        let all_sides = TerrainLod::NORTH | TerrainLod::EAST | TerrainLod::SOUTH | TerrainLod::WEST;
+        let stitched = TerrainLod::patch_indices(VERTEX_COUNT, 0, all_sides);
+        assert_eq!(full.len() - stitched.len(), (TerrainLod::PATCH_QUADS * 2 * 3) as usize);
+    }
+
+    #[test]
+    fn the_coarsest_level_ignores_the_stitch_mask() {
+        let level = TerrainLod::LEVELS - 1;
+        assert_eq!(TerrainLod::patch_indices(VERTEX_COUNT, level, TerrainLod::STITCH_COMBINATIONS - 1),
+                   TerrainLod::patch_indices(VERTEX_COUNT, level, 0));
+    }
+
+    #[test]
+    fn levels_grow_with_distance_and_neighbours_stay_within_one() {
+        let patch_size = 100.0;
+        assert_eq!(TerrainLod::level_for_patch(50.0, 50.0, 0.0, 0.0, patch_size), 0);
+        assert_eq!(TerrainLod::level_for_patch(0.0, 0.0, 1.0e6, 0.0, patch_size), TerrainLod::LEVELS - 1);
+        let mut previous = 0;
+        for patch in 0..200 {
+            let level = TerrainLod::level_for_patch(0.0, 0.0, patch as f32 * patch_size, 0.0, patch_size);
+            assert!(level >= previous && level <= previous + 1);
+            previous = level;
+        }
+    }
+
+    #[test]
+    fn normals_match_across_tile_borders() {
+        let config = TerrainConfig { size: 100.0, vertex_count: 33, max_height: 40.0 };
+        let grid_square_size = config.size / (config.vertex_count - 1) as f32;
+        let west = Terrain::sample_bordered_heights(&config, 0.0, 0.0, &Hills);
+        let east = Terrain::sample_bordered_heights(&config, config.size, 0.0, &Hills);
+        let last = config.vertex_count as i32;
+        for z in 1..last + 1 {
+            assert_eq!(west.get(last, z), east.get(1, z));
+            let west_normal = Terrain::calculate_normal(&west, grid_square_size, last, z);
+            let east_normal = Terrain::calculate_normal(&east, grid_square_size, 1, z);
+            assert!((west_normal - east_normal).magnitude() < 1e-5, "z {}: {:?} {:?}", z, west_normal, east_normal);
+        }
+    }
+}
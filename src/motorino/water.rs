@@ -0,0 +1,337 @@
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use super::camera::Camera;
+use super::cgmath::{Matrix4, Vector3, Vector4};
+use super::cgmath::prelude::*;
+use super::components::{Transform, Water};
+use super::debugui::DebugInfo;
+use super::frameuniforms::FrameUniformUploader;
+use super::gl;
+use super::gl::types::*;
+use super::models::Loader;
+use super::renderers::{Attachment, Fbo, ScenePass};
+use super::resources::ResourceLoader;
+use super::shaders::ShaderProgram;
+use super::specs::{Read, ReadStorage, System, Write};
+use super::{UpdateDeltaTime, WindowSize};
+
+/// The textures the water passes rendered this frame, none while there is no water
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WaterTextures {
+    pub reflection_texture: GLuint,
+    pub refraction_texture: GLuint,
+    pub refraction_depth_texture: GLuint
+}
+
+enum WaterPassKind {
+    Reflection(Fbo),
+    Refraction(Fbo),
+    Screen
+}
+
+/// Points the terrain, entity and skybox renderers that follow it at one of the water textures,
+/// clipped at the surface of the water nearest the camera. The reflection is drawn from under the
+/// surface, see `ScenePass::camera`. The screen pass puts everything back for the frame itself.
+pub struct WaterPassRenderer {
+    kind: WaterPassKind,
+    // the textures are this many times smaller than the screen each way
+    resolution_divisor: u32
+}
+
+impl WaterPassRenderer {
+    // how far past the surface is still drawn, stops a gap showing along the shore when distorted
+    const CLIP_OFFSET: f32 = 0.5;
+
+    // the reflection is distorted the most, so it gets away with fewer pixels
+    const REFLECTION_DIVISOR: u32 = 2;
+    const REFRACTION_DIVISOR: u32 = 1;
+
+    pub fn reflection(window_size: &WindowSize) -> Result<WaterPassRenderer, String> {
+        let fbo = WaterPassRenderer::create_fbo(window_size, WaterPassRenderer::REFLECTION_DIVISOR)?;
+        Ok(WaterPassRenderer { kind: WaterPassKind::Reflection(fbo), resolution_divisor: WaterPassRenderer::REFLECTION_DIVISOR })
+    }
+
+    pub fn refraction(window_size: &WindowSize) -> Result<WaterPassRenderer, String> {
+        let fbo = WaterPassRenderer::create_fbo(window_size, WaterPassRenderer::REFRACTION_DIVISOR)?;
+        Ok(WaterPassRenderer { kind: WaterPassKind::Refraction(fbo), resolution_divisor: WaterPassRenderer::REFRACTION_DIVISOR })
+    }
+
+    pub fn screen() -> WaterPassRenderer {
+        WaterPassRenderer { kind: WaterPassKind::Screen, resolution_divisor: 1 }
+    }
+
+    // the depth is sampled too, to find how deep the water is under each pixel
+    fn create_fbo(window_size: &WindowSize, resolution_divisor: u32) -> Result<Fbo, String> {
+        Fbo::new(window_size.width / resolution_divisor, window_size.height / resolution_divisor,
+                 Attachment::Texture(gl::RGB8), Attachment::Texture(gl::DEPTH_COMPONENT24))
+    }
+
+    /// Follows the screen when the window is resized
+    fn resize(&mut self, window_size: &WindowSize) -> Result<(), String> {
+        let (width, height) = (window_size.width / self.resolution_divisor, window_size.height / self.resolution_divisor);
+        match self.kind {
+            WaterPassKind::Reflection(ref mut fbo) | WaterPassKind::Refraction(ref mut fbo) => fbo.resize(width, height),
+            WaterPassKind::Screen => Ok(())
+        }
+    }
+
+    fn begin(fbo: &Fbo) {
+        fbo.bind();
+        unsafe {
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Enable(gl::CLIP_DISTANCE0);
+            gl::ClearColor(1.0, 1.0, 1.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    fn nearest_water_height(camera: &Camera, water: &ReadStorage<Water>, transform: &ReadStorage<Transform>) -> Option<f32> {
+        use super::specs::Join;
+        let camera_position = Vector3::new(camera.position.x, 0.0, camera.position.z);
+        (water, transform).join()
+            .map(|(_water, transform)| {
+                let position = Vector3::new(transform.position.x, 0.0, transform.position.z);
+                (position.distance2(camera_position), transform.position.y)
+            })
+            // a water placed at a NaN position can't be nearest
+            .filter(|&(distance, _height)| !distance.is_nan())
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+            .map(|(_distance, height)| height)
+    }
+}
+
+impl<'a> System<'a> for WaterPassRenderer {
+    type SystemData = (ReadStorage<'a, Water>,
+                       ReadStorage<'a, Transform>,
+                       Read<'a, Camera>,
+                       Write<'a, ScenePass>,
+                       Write<'a, WaterTextures>,
+                       Read<'a, WindowSize>,
+                       Write<'a, DebugInfo>);
+
+    fn run(&mut self, (water, transform, camera, mut scene_pass, mut water_textures, window_size, mut debug_info): Self::SystemData) {
+        if let Err(message) = self.resize(&window_size) {
+            debug_info.framebuffer_error = Some(message);
+        }
+
+        match (&self.kind, WaterPassRenderer::nearest_water_height(&camera, &water, &transform)) {
+            (WaterPassKind::Reflection(fbo), Some(water_height)) => {
+                WaterPassRenderer::begin(fbo);
+                *scene_pass = ScenePass {
+                    enabled: true,
+                    target: fbo.target(),
+                    clip_plane: Vector4::new(0.0, 1.0, 0.0, -water_height + WaterPassRenderer::CLIP_OFFSET),
+                    reflected_at: Some(water_height),
+                    counts_stats: false
+                };
+                water_textures.reflection_texture = fbo.colour_texture();
+            },
+            (WaterPassKind::Refraction(fbo), Some(water_height)) => {
+                WaterPassRenderer::begin(fbo);
+                *scene_pass = ScenePass {
+                    enabled: true,
+                    target: fbo.target(),
+                    clip_plane: Vector4::new(0.0, -1.0, 0.0, water_height + WaterPassRenderer::CLIP_OFFSET),
+                    reflected_at: None,
+                    counts_stats: false
+                };
+                water_textures.refraction_texture = fbo.colour_texture();
+                water_textures.refraction_depth_texture = fbo.depth_texture();
+            },
+            (&WaterPassKind::Screen, _) => {
+                unsafe {
+                    gl::Disable(gl::CLIP_DISTANCE0);
+                }
+                // the frame itself, drawn to the screen
+                *scene_pass = ScenePass::default();
+            },
+            // nothing to reflect, the scene renderers skip this pass
+            (_, None) => {
+                scene_pass.enabled = false;
+                *water_textures = WaterTextures::default();
+            }
+        }
+    }
+}
+
+/// Draws every `Water` as a quad mixing the reflection and refraction textures, rippled by a
+/// distortion map and lit by the sun through a normal map
+pub struct WaterRenderer {
+    water_shader: ShaderProgram,
+    quad_vao: GLuint,
+    quad_vbo: GLuint,
+    dudv_texture: GLuint,
+    normal_texture: GLuint,
+    move_factor: f32
+}
+
+impl<'a> System<'a> for WaterRenderer {
+
+    type SystemData = (ReadStorage<'a, Water>,
+                       ReadStorage<'a, Transform>,
+                       Read<'a, WaterTextures>,
+                       Read<'a, ScenePass>,
+                       Read<'a, WindowSize>,
+                       Read<'a, UpdateDeltaTime>,
+                       Write<'a, DebugInfo>);
+
+    fn run(&mut self, (water, transform, water_textures, scene_pass, window_size, dt, mut debug_info): Self::SystemData) {
+        self.move_factor = (self.move_factor + dt.0 * WaterRenderer::WAVE_SPEED) % 1.0;
+        if water_textures.reflection_texture == 0 {
+            return;
+        }
+        scene_pass.target.bind(&window_size);
+        let blend_enabled = self.prepare();
+        self.water_shader.start();
+        unsafe {
+            self.water_shader.set_float(c_str!("move_factor"), self.move_factor);
+        }
+        self.bind_textures(&water_textures);
+        unsafe {
+            gl::BindVertexArray(self.quad_vao);
+            gl::EnableVertexAttribArray(0); // positions
+        }
+
+        use super::specs::Join;
+        for (water, transform) in (&water, &transform).join() {
+            let transformation_matrix = Matrix4::from_translation(transform.position) * Matrix4::from_scale(water.size * 0.5);
+            debug_info.current_frame_draw_calls += 1;
+            debug_info.current_frame_triangle_count += 2;
+            unsafe {
+                self.water_shader.set_mat4(c_str!("transformation_matrix"), &transformation_matrix);
+                gl::DrawArrays(gl::TRIANGLES, 0, 6);
+            }
+        }
+
+        unsafe {
+            gl::DisableVertexAttribArray(0);
+            gl::BindVertexArray(0);
+            if !blend_enabled {
+                gl::Disable(gl::BLEND);
+            }
+        }
+        self.water_shader.stop();
+    }
+}
+
+impl WaterRenderer {
+
+    const VERTEX_SHADER: &'static str = "shaders/water.vert";
+    const FRAGMENT_SHADER: &'static str = "shaders/water.frag";
+    // texture repeats per second the ripples move
+    const WAVE_SPEED: f32 = 0.03;
+    const WAVE_MAP_SIZE: u32 = 256;
+
+    pub fn new(resource_loader: &ResourceLoader) -> Result<WaterRenderer, String> {
+        let water_shader = FrameUniformUploader::shader_program(resource_loader, WaterRenderer::VERTEX_SHADER, WaterRenderer::FRAGMENT_SHADER)
+            .map_err(|e| e.to_string())?;
+        // a unit square in the xz plane, scaled to the size of each water
+        const quad_positions: [f32; 12] = [-1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+        let mut quad_vao = 0;
+        let mut quad_vbo = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut quad_vao);
+            gl::BindVertexArray(quad_vao);
+            gl::GenBuffers(1, &mut quad_vbo);
+            gl::BindBuffer(gl::ARRAY_BUFFER, quad_vbo);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (quad_positions.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           &quad_positions[0] as *const f32 as *const c_void,
+                           gl::STATIC_DRAW);
+            gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, 0, ptr::null());
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+        let (dudv_map, normal_map) = WaterRenderer::wave_maps(WaterRenderer::WAVE_MAP_SIZE);
+        let size = WaterRenderer::WAVE_MAP_SIZE;
+        let dudv_texture = Loader::upload_texture(size, size, gl::RGB, gl::RGB, &dudv_map);
+        let normal_texture = Loader::upload_texture(size, size, gl::RGB, gl::RGB, &normal_map);
+        Ok(WaterRenderer { water_shader, quad_vao, quad_vbo, dudv_texture, normal_texture, move_factor: 0.0 })
+    }
+
+    /// A distortion map and a matching normal map of a few sine waves. Every wave repeats a whole
+    /// number of times across the texture so both tile without seams.
+    fn wave_maps(size: u32) -> (Vec<u8>, Vec<u8>) {
+        // repeats across the texture in x and z, amplitude and phase
+        const waves: [(f32, f32, f32, f32); 7] = [
+            (1.0, 0.0, 1.0, 0.0),
+            (0.0, 1.0, 0.9, 1.3),
+            (2.0, 1.0, 0.5, 2.1),
+            (-1.0, 3.0, 0.35, 0.7),
+            (3.0, -2.0, 0.25, 4.0),
+            (5.0, 4.0, 0.15, 2.9),
+            (-7.0, 5.0, 0.1, 5.3)];
+        let max_slope: f32 = waves.iter().map(|&(fx, fz, amplitude, _)| amplitude * 2.0 * PI * (fx.abs() + fz.abs())).sum();
+        let to_byte = |value: f32| ((value * 0.5 + 0.5).clamp(0.0, 1.0) * 255.0) as u8;
+
+        let mut dudv_map = Vec::with_capacity((size * size * 3) as usize);
+        let mut normal_map = Vec::with_capacity((size * size * 3) as usize);
+        for z in 0..size {
+            for x in 0..size {
+                let u = x as f32 / size as f32;
+                let v = z as f32 / size as f32;
+                let (mut slope_x, mut slope_z) = (0.0, 0.0);
+                for &(fx, fz, amplitude, phase) in waves.iter() {
+                    let derivative = amplitude * 2.0 * PI * (2.0 * PI * (fx * u + fz * v) + phase).cos();
+                    slope_x += derivative * fx;
+                    slope_z += derivative * fz;
+                }
+                slope_x /= max_slope;
+                slope_z /= max_slope;
+                dudv_map.extend_from_slice(&[to_byte(slope_x), to_byte(slope_z), 0]);
+                let normal = Vector3::new(-slope_x, 1.0, -slope_z).normalize();
+                normal_map.extend_from_slice(&[to_byte(normal.x), to_byte(normal.y), to_byte(normal.z)]);
+            }
+        }
+        (dudv_map, normal_map)
+    }
+
+    /// The camera, the sun and fog come from the `FrameUniforms` block. Returns whether blending
+    /// was already on, to be left on afterwards.
+    fn prepare(&self) -> bool {
+        self.water_shader.start();
+        let blend_enabled = unsafe { gl::IsEnabled(gl::BLEND) == gl::TRUE };
+        unsafe {
+            self.water_shader.set_int(c_str!("reflection_sampler"), 0);
+            self.water_shader.set_int(c_str!("refraction_sampler"), 1);
+            self.water_shader.set_int(c_str!("depth_sampler"), 2);
+            self.water_shader.set_int(c_str!("dudv_sampler"), 3);
+            self.water_shader.set_int(c_str!("normal_sampler"), 4);
+            // shallow water fades into the ground beneath
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        self.water_shader.stop();
+        blend_enabled
+    }
+
+    fn bind_textures(&self, water_textures: &WaterTextures) {
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, water_textures.reflection_texture);
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, water_textures.refraction_texture);
+            gl::ActiveTexture(gl::TEXTURE2);
+            gl::BindTexture(gl::TEXTURE_2D, water_textures.refraction_depth_texture);
+            gl::ActiveTexture(gl::TEXTURE3);
+            gl::BindTexture(gl::TEXTURE_2D, self.dudv_texture);
+            gl::ActiveTexture(gl::TEXTURE4);
+            gl::BindTexture(gl::TEXTURE_2D, self.normal_texture);
+        }
+    }
+}
+
+impl Drop for WaterRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.quad_vao);
+            gl::DeleteBuffers(1, &self.quad_vbo);
+            gl::DeleteTextures(1, &self.dudv_texture);
+            gl::DeleteTextures(1, &self.normal_texture);
+        }
+    }
+}